@@ -0,0 +1,265 @@
+use rustyboy::commands;
+use rustyboy::cpu::image::save_framebuffer_png;
+use rustyboy::headless::{run_until, StopCondition, StopReason};
+use rustyboy::symbols::mapped_bank;
+use rustyboy::{Cpu, Model, RecordingMode, SerialCapture, Symbols};
+use std::fmt::Write;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+const USAGE: &str =
+    "usage: rustyboy-headless [--model MODEL] [--frames N] [--until-serial TEXT] [--until-pc ADDR] \
+[--until-loop] [--until-breakpoint] [--png PATH] [--json PATH] [--trace PATH] [--sym PATH] [--record-wav PATH] [--record-channels] ROM
+       rustyboy-headless disasm [--sym PATH] ROM [-o OUTPUT]
+       rustyboy-headless debug [--model MODEL] [--sym PATH] [--gdb PORT] ROM";
+
+const DEFAULT_FRAMES: u32 = 60 * 60;
+
+// Exit code when a stop condition was given but none was met in time.
+const EXIT_TIMEOUT: i32 = 3;
+
+struct Options {
+    rom: Option<String>,
+    model: Model,
+    frames: u32,
+    conditions: Vec<StopCondition>,
+    png: Option<String>,
+    json: Option<String>,
+    trace: Option<String>,
+    sym: Option<String>,
+    record_wav: Option<String>,
+    record_channels: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            rom: None,
+            model: Model::default(),
+            frames: DEFAULT_FRAMES,
+            conditions: Vec::new(),
+            png: None,
+            json: None,
+            trace: None,
+            sym: None,
+            record_wav: None,
+            record_channels: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--model" => options.model = args.next().ok_or("--model needs a name")?.parse()?,
+                "--frames" => {
+                    let frames = args.next().ok_or("--frames needs a count")?;
+                    options.frames = frames
+                        .parse()
+                        .map_err(|_| format!("invalid frame count: {}", frames))?;
+                }
+                "--until-serial" => {
+                    let text = args.next().ok_or("--until-serial needs text")?;
+                    options.conditions.push(StopCondition::SerialText(text));
+                }
+                "--until-pc" => {
+                    let address = args.next().ok_or("--until-pc needs an address")?;
+                    let digits = address.trim_start_matches("0x").trim_start_matches('$');
+                    let pc = u16::from_str_radix(digits, 16)
+                        .map_err(|_| format!("invalid address: {}", address))?;
+                    options.conditions.push(StopCondition::Pc(pc));
+                }
+                "--until-loop" => options.conditions.push(StopCondition::InfiniteLoop),
+                "--until-breakpoint" => options.conditions.push(StopCondition::Breakpoint),
+                "--png" => options.png = Some(args.next().ok_or("--png needs a path")?),
+                "--json" => options.json = Some(args.next().ok_or("--json needs a path")?),
+                "--trace" => options.trace = Some(args.next().ok_or("--trace needs a path")?),
+                "--sym" => options.sym = Some(args.next().ok_or("--sym needs a path")?),
+                "--record-wav" => {
+                    options.record_wav = Some(args.next().ok_or("--record-wav needs a path")?)
+                }
+                "--record-channels" => options.record_channels = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+                _ => options.rom = Some(arg),
+            }
+        }
+        if options.rom.is_none() {
+            return Err("no ROM given".to_string());
+        }
+        if options.record_channels && options.record_wav.is_none() {
+            return Err("--record-channels needs --record-wav".to_string());
+        }
+        Ok(options)
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn report(cpu: &Cpu, reason: &StopReason, frames: u32, cycles: u64, serial: &str) -> String {
+    let registers = cpu.registers();
+    let flags = registers.f;
+    let error = match reason {
+        StopReason::Error(error) => json_string(&error.to_string()),
+        _ => "null".to_string(),
+    };
+    format!(
+        concat!(
+            "{{\n",
+            "  \"stop\": \"{}\",\n",
+            "  \"error\": {},\n",
+            "  \"frames\": {},\n",
+            "  \"cycles\": {},\n",
+            "  \"registers\": {{\"a\": {}, \"f\": {}, \"b\": {}, \"c\": {}, \"d\": {}, \"e\": {}, ",
+            "\"h\": {}, \"l\": {}, \"sp\": {}, \"pc\": {}}},\n",
+            "  \"flags\": {{\"z\": {}, \"n\": {}, \"h\": {}, \"c\": {}}},\n",
+            "  \"serial\": {}\n",
+            "}}\n"
+        ),
+        reason.name(),
+        error,
+        frames,
+        cycles,
+        registers.a,
+        u8::from(flags),
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+        cpu.sp(),
+        cpu.pc(),
+        flags.zero,
+        flags.subtract,
+        flags.half_carry,
+        flags.carry,
+        json_string(serial),
+    )
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    let subcommand = args.peek().cloned();
+    let result = match subcommand.as_deref() {
+        Some("disasm") => Some(commands::disasm(args.by_ref().skip(1))),
+        Some("debug") => Some(commands::debug(args.by_ref().skip(1))),
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(error) = result {
+            eprintln!("{}\n{}", error, USAGE);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let path = options.rom.as_deref().unwrap_or_default();
+    let game_rom = std::fs::read(path).unwrap_or_else(|error| {
+        eprintln!("Could not read {}: {}", path, error);
+        std::process::exit(1);
+    });
+
+    // Symbols are only read when asked for, since they change the trace
+    // format.
+    let symbols = options.sym.as_ref().map(|path| {
+        Symbols::load(Path::new(path)).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        })
+    });
+
+    let serial = SerialCapture::new();
+    let mut cpu = Cpu::with_model(options.model, None, game_rom);
+    cpu.set_serial_sink(Box::new(serial.clone()));
+
+    // Traces are meant to be diffed against Gameboy Doctor reference logs,
+    // which are taken with LY stubbed.
+    if let Some(path) = &options.trace {
+        let file = File::create(path).unwrap_or_else(|error| {
+            eprintln!("Could not create {}: {}", path, error);
+            std::process::exit(1);
+        });
+        cpu.bus_mut().set_ly_stub(true);
+        cpu.set_trace_log(Some(Box::new(BufWriter::new(file))));
+    }
+    if let Some(symbols) = &symbols {
+        cpu.set_trace_symbols(Some(symbols.clone()));
+    }
+
+    if let Some(path) = &options.record_wav {
+        let mode = if options.record_channels {
+            RecordingMode::PerChannel
+        } else {
+            RecordingMode::Mixed
+        };
+        if let Err(error) = cpu.start_audio_recording(path, mode) {
+            eprintln!("Could not record to {}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+
+    let result = run_until(&mut cpu, &serial, options.frames, &options.conditions);
+    // Dropping the log flushes it.
+    cpu.set_trace_log(None);
+    if let Err(error) = cpu.stop_audio_recording() {
+        eprintln!("Audio recording failed: {}", error);
+        std::process::exit(1);
+    }
+
+    if let Some(path) = &options.png {
+        if let Err(error) = save_framebuffer_png(Path::new(path), cpu.framebuffer()) {
+            eprintln!("Could not save {}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+    let json = report(
+        &cpu,
+        &result.reason,
+        result.frames,
+        result.cycles,
+        &serial.text(),
+    );
+    match &options.json {
+        Some(path) => {
+            if let Err(error) = std::fs::write(path, json) {
+                eprintln!("Could not write {}: {}", path, error);
+                std::process::exit(1);
+            }
+        }
+        None => print!("{}", json),
+    }
+
+    match result.reason {
+        StopReason::Error(error) => {
+            let pc = error.pc();
+            match symbols.and_then(|symbols| symbols.describe(mapped_bank(pc), pc)) {
+                Some(name) => eprintln!("Emulation stopped: {} <{}>", error, name),
+                None => eprintln!("Emulation stopped: {}", error),
+            }
+            std::process::exit(1);
+        }
+        StopReason::FrameLimit if !options.conditions.is_empty() => {
+            std::process::exit(EXIT_TIMEOUT)
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,89 @@
+//! Subcommands both binaries offer, so `rustyboy` and `rustyboy-headless`
+//! parse and run them the same way. Each takes the arguments after the
+//! subcommand's name.
+
+use std::path::Path;
+
+use crate::{debugger, rom_disasm, Cpu, Model, Symbols};
+
+/// Symbols from `--sym`, or from the `.sym` next to the ROM if there is one.
+pub fn load_symbols(rom: &str, path: Option<String>) -> Result<Symbols, String> {
+    match path {
+        Some(path) => Symbols::load(Path::new(&path)),
+        None => {
+            let path = Symbols::path_for_rom(Path::new(rom));
+            if path.exists() {
+                Symbols::load(&path)
+            } else {
+                Ok(Symbols::new())
+            }
+        }
+    }
+}
+
+/// `disasm [--sym PATH] ROM [-o OUTPUT]` writes RGBDS source for the ROM to
+/// OUTPUT, or to stdout.
+pub fn disasm(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut rom = None;
+    let mut output = None;
+    let mut sym = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or("-o needs a path")?),
+            "--sym" => sym = Some(args.next().ok_or("--sym needs a path")?),
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ => rom = Some(arg),
+        }
+    }
+    let rom = rom.ok_or("disasm needs a ROM")?;
+    let bytes =
+        std::fs::read(&rom).map_err(|error| format!("Could not read {}: {}", rom, error))?;
+    let symbols = load_symbols(&rom, sym)?;
+    let source = rom_disasm::disassemble_rom(&bytes, &symbols);
+    match output {
+        Some(path) => std::fs::write(&path, source)
+            .map_err(|error| format!("Could not write {}: {}", path, error)),
+        None => {
+            print!("{}", source);
+            Ok(())
+        }
+    }
+}
+
+/// `debug [--model MODEL] [--sym PATH] [--gdb PORT] ROM` runs the ROM under
+/// the debugger REPL on stdin and stdout, or waits for GDB on a local port.
+pub fn debug(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut rom = None;
+    let mut model = Model::default();
+    let mut gdb_port = None;
+    let mut sym = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--model" => model = args.next().ok_or("--model needs a name")?.parse()?,
+            "--sym" => sym = Some(args.next().ok_or("--sym needs a path")?),
+            "--gdb" => {
+                let port = args.next().ok_or("--gdb needs a port")?;
+                let port: u16 = port
+                    .parse()
+                    .map_err(|_| format!("invalid port: {}", port))?;
+                gdb_port = Some(port);
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {}", arg)),
+            _ => rom = Some(arg),
+        }
+    }
+    let rom = rom.ok_or("debug needs a ROM")?;
+    let bytes =
+        std::fs::read(&rom).map_err(|error| format!("Could not read {}: {}", rom, error))?;
+    let symbols = load_symbols(&rom, sym)?;
+    let mut cpu = Cpu::with_model(model, None, bytes);
+    if let Some(port) = gdb_port {
+        let address = format!("127.0.0.1:{}", port);
+        eprintln!("Waiting for GDB on {}", address);
+        return debugger::gdb::serve(&mut cpu, &address, symbols)
+            .map_err(|error| error.to_string());
+    }
+    let stdin = std::io::stdin();
+    debugger::repl::run(&mut cpu, symbols, stdin.lock(), &mut std::io::stdout())
+        .map_err(|error| error.to_string())
+}
@@ -0,0 +1,829 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::state::{StateError, StateReader, StateWriter};
+use super::wav::WavWriter;
+
+pub const APU_BEGIN: usize = 0xFF10;
+pub const APU_END: usize = 0xFF3F;
+pub const WAVE_RAM_BEGIN: usize = 0xFF30;
+pub const NR52_ADDRESS: usize = 0xFF26;
+
+pub const SAMPLE_RATE: u32 = 48_000;
+pub const CPU_CLOCK: u32 = 4_194_304;
+
+const FRAME_SEQUENCER_PERIOD: u32 = CPU_CLOCK / 512;
+const DEFAULT_BUFFER_CAPACITY: usize = (SAMPLE_RATE / 10) as usize;
+
+const NR10: usize = 0x00;
+const NR11: usize = 0x01;
+const NR12: usize = 0x02;
+const NR13: usize = 0x03;
+const NR14: usize = 0x04;
+const NR21: usize = 0x06;
+const NR22: usize = 0x07;
+const NR23: usize = 0x08;
+const NR24: usize = 0x09;
+const NR30: usize = 0x0A;
+const NR31: usize = 0x0B;
+const NR32: usize = 0x0C;
+const NR33: usize = 0x0D;
+const NR34: usize = 0x0E;
+const NR41: usize = 0x10;
+const NR42: usize = 0x11;
+const NR43: usize = 0x12;
+const NR44: usize = 0x13;
+const NR50: usize = 0x14;
+const NR51: usize = 0x15;
+const NR52: usize = 0x16;
+
+// Bits that always read back as 1, indexed from 0xFF10.
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+const DUTY_PATTERNS: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+/// Stereo samples produced by the APU, waiting to be consumed by a frontend.
+/// When the buffer is full the oldest sample is dropped.
+pub struct AudioBuffer {
+    samples: VecDeque<(f32, f32)>,
+    capacity: usize,
+}
+
+impl AudioBuffer {
+    pub fn new(capacity: usize) -> AudioBuffer {
+        AudioBuffer {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, left: f32, right: f32) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((left, right));
+    }
+
+    pub fn pop(&mut self) -> Option<(f32, f32)> {
+        self.samples.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn fill_level(&self) -> f32 {
+        self.samples.len() as f32 / self.capacity as f32
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+#[derive(Default)]
+struct LengthCounter {
+    counter: u16,
+}
+
+impl LengthCounter {
+    // Returns false once the counter expires and the channel should turn off.
+    fn clock(&mut self, enabled: bool) -> bool {
+        if enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter != 0;
+        }
+        true
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.counter);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.counter = reader.read_u16()?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Envelope {
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn trigger(&mut self, register: u8) {
+        self.volume = register >> 4;
+        self.timer = register & 0x07;
+    }
+
+    fn clock(&mut self, register: u8) {
+        let period = register & 0x07;
+        if period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = period;
+            let increase = register & 0x08 != 0;
+            if increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.volume);
+        writer.write_u8(self.timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.volume = reader.read_u8()?;
+        self.timer = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct SquareChannel {
+    enabled: bool,
+    timer: u32,
+    duty_position: usize,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep_enabled: bool,
+    sweep_timer: u8,
+    shadow_frequency: u16,
+}
+
+impl SquareChannel {
+    fn period(frequency: u16) -> u32 {
+        (2048 - frequency as u32) * 4
+    }
+
+    fn tick(&mut self, frequency: u16) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = SquareChannel::period(frequency);
+            self.duty_position = (self.duty_position + 1) % 8;
+        }
+    }
+
+    fn output(&self, duty: u8) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_PATTERNS[duty as usize][self.duty_position] * self.envelope.volume
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u32(self.timer);
+        writer.write_u8(self.duty_position as u8);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        writer.write_bool(self.sweep_enabled);
+        writer.write_u8(self.sweep_timer);
+        writer.write_u16(self.shadow_frequency);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.timer = reader.read_u32()?;
+        self.duty_position = (reader.read_u8()? % 8) as usize;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        self.sweep_enabled = reader.read_bool()?;
+        self.sweep_timer = reader.read_u8()?;
+        self.shadow_frequency = reader.read_u16()?;
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct WaveChannel {
+    enabled: bool,
+    timer: u32,
+    position: usize,
+    length: LengthCounter,
+}
+
+impl WaveChannel {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u32(self.timer);
+        writer.write_u8(self.position as u8);
+        self.length.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.timer = reader.read_u32()?;
+        self.position = (reader.read_u8()? % 32) as usize;
+        self.length.load_state(reader)
+    }
+}
+
+#[derive(Default)]
+struct NoiseChannel {
+    enabled: bool,
+    timer: u32,
+    lfsr: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    fn period(register: u8) -> u32 {
+        NOISE_DIVISORS[(register & 0x07) as usize] << (register >> 4)
+    }
+
+    fn tick(&mut self, register: u8) {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = NoiseChannel::period(register);
+            let feedback = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+            self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+            if register & 0x08 != 0 {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (feedback << 6);
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 0x01 != 0 {
+            return 0;
+        }
+        self.envelope.volume
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u32(self.timer);
+        writer.write_u16(self.lfsr);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = reader.read_bool()?;
+        self.timer = reader.read_u32()?;
+        self.lfsr = reader.read_u16()?;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RecordingMode {
+    Mixed,
+    PerChannel,
+}
+
+struct Recording {
+    mode: RecordingMode,
+    writers: Vec<WavWriter>,
+    error: Option<io::Error>,
+}
+
+pub struct Apu {
+    registers: [u8; 0x20],
+    wave_ram: [u8; 0x10],
+    square1: SquareChannel,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    frame_sequencer_timer: u32,
+    frame_sequencer_step: u8,
+    sample_timer: u32,
+    buffer: AudioBuffer,
+    recording: Option<Recording>,
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            registers: [0; 0x20],
+            wave_ram: [0; 0x10],
+            square1: SquareChannel::default(),
+            square2: SquareChannel::default(),
+            wave: WaveChannel::default(),
+            noise: NoiseChannel::default(),
+            frame_sequencer_timer: FRAME_SEQUENCER_PERIOD,
+            frame_sequencer_step: 0,
+            sample_timer: 0,
+            buffer: AudioBuffer::new(DEFAULT_BUFFER_CAPACITY),
+            recording: None,
+        }
+    }
+
+    pub fn buffer(&self) -> &AudioBuffer {
+        &self.buffer
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut AudioBuffer {
+        &mut self.buffer
+    }
+
+    /// Leaves channel 1 on with its envelope run down to silence, which is how
+    /// the boot chime ends and what NR52 reports afterwards.
+    pub fn end_boot_chime(&mut self) {
+        self.square1.enabled = true;
+        self.square1.envelope.volume = 0;
+    }
+
+    fn powered(&self) -> bool {
+        self.registers[NR52] & 0x80 != 0
+    }
+
+    pub fn read_register(&self, address: usize) -> u8 {
+        if address >= WAVE_RAM_BEGIN {
+            return self.wave_ram[address - WAVE_RAM_BEGIN];
+        }
+        let index = address - APU_BEGIN;
+        if index == NR52 {
+            return (self.registers[NR52] & 0x80)
+                | READ_MASKS[NR52]
+                | (self.square1.enabled as u8)
+                | (self.square2.enabled as u8) << 1
+                | (self.wave.enabled as u8) << 2
+                | (self.noise.enabled as u8) << 3;
+        }
+        self.registers[index] | READ_MASKS[index]
+    }
+
+    /// A register as last written, including the bits that read back as 1.
+    pub fn written_register(&self, address: usize) -> u8 {
+        match address - APU_BEGIN {
+            NR52 => self.read_register(address),
+            index if address < WAVE_RAM_BEGIN => self.registers[index],
+            _ => self.wave_ram[address - WAVE_RAM_BEGIN],
+        }
+    }
+
+    pub fn write_register(&mut self, address: usize, value: u8) {
+        if address >= WAVE_RAM_BEGIN {
+            self.wave_ram[address - WAVE_RAM_BEGIN] = value;
+            return;
+        }
+        let index = address - APU_BEGIN;
+        if index == NR52 {
+            self.write_power(value);
+            return;
+        }
+        if !self.powered() {
+            return;
+        }
+        self.registers[index] = value;
+
+        match index {
+            NR11 => self.square1.length.counter = 64 - (value & 0x3F) as u16,
+            NR21 => self.square2.length.counter = 64 - (value & 0x3F) as u16,
+            NR31 => self.wave.length.counter = 256 - value as u16,
+            NR41 => self.noise.length.counter = 64 - (value & 0x3F) as u16,
+            NR12 if value & 0xF8 == 0 => self.square1.enabled = false,
+            NR22 if value & 0xF8 == 0 => self.square2.enabled = false,
+            NR30 if value & 0x80 == 0 => self.wave.enabled = false,
+            NR42 if value & 0xF8 == 0 => self.noise.enabled = false,
+            NR14 if value & 0x80 != 0 => self.trigger_square1(),
+            NR24 if value & 0x80 != 0 => self.trigger_square2(),
+            NR34 if value & 0x80 != 0 => self.trigger_wave(),
+            NR44 if value & 0x80 != 0 => self.trigger_noise(),
+            _ => {}
+        }
+    }
+
+    fn write_power(&mut self, value: u8) {
+        let was_powered = self.powered();
+        if was_powered && value & 0x80 == 0 {
+            self.registers = [0; 0x20];
+            self.square1 = SquareChannel::default();
+            self.square2 = SquareChannel::default();
+            self.wave = WaveChannel::default();
+            self.noise = NoiseChannel::default();
+        } else if !was_powered && value & 0x80 != 0 {
+            self.frame_sequencer_step = 0;
+        }
+        self.registers[NR52] = value & 0x80;
+    }
+
+    fn frequency(&self, low: usize, high: usize) -> u16 {
+        (self.registers[high] as u16 & 0x07) << 8 | self.registers[low] as u16
+    }
+
+    fn trigger_square1(&mut self) {
+        let frequency = self.frequency(NR13, NR14);
+        let sweep = self.registers[NR10];
+        let channel = &mut self.square1;
+        channel.enabled = self.registers[NR12] & 0xF8 != 0;
+        if channel.length.counter == 0 {
+            channel.length.counter = 64;
+        }
+        channel.timer = SquareChannel::period(frequency);
+        channel.envelope.trigger(self.registers[NR12]);
+
+        channel.shadow_frequency = frequency;
+        let period = (sweep >> 4) & 0x07;
+        channel.sweep_timer = if period == 0 { 8 } else { period };
+        channel.sweep_enabled = period != 0 || sweep & 0x07 != 0;
+        if sweep & 0x07 != 0 && self.sweep_frequency() > 2047 {
+            self.square1.enabled = false;
+        }
+    }
+
+    fn trigger_square2(&mut self) {
+        let frequency = self.frequency(NR23, NR24);
+        let channel = &mut self.square2;
+        channel.enabled = self.registers[NR22] & 0xF8 != 0;
+        if channel.length.counter == 0 {
+            channel.length.counter = 64;
+        }
+        channel.timer = SquareChannel::period(frequency);
+        channel.envelope.trigger(self.registers[NR22]);
+    }
+
+    fn trigger_wave(&mut self) {
+        let frequency = self.frequency(NR33, NR34);
+        let channel = &mut self.wave;
+        channel.enabled = self.registers[NR30] & 0x80 != 0;
+        if channel.length.counter == 0 {
+            channel.length.counter = 256;
+        }
+        channel.timer = (2048 - frequency as u32) * 2;
+        channel.position = 0;
+    }
+
+    fn trigger_noise(&mut self) {
+        let channel = &mut self.noise;
+        channel.enabled = self.registers[NR42] & 0xF8 != 0;
+        if channel.length.counter == 0 {
+            channel.length.counter = 64;
+        }
+        channel.timer = NoiseChannel::period(self.registers[NR43]);
+        channel.envelope.trigger(self.registers[NR42]);
+        channel.lfsr = 0x7FFF;
+    }
+
+    fn sweep_frequency(&self) -> u16 {
+        let sweep = self.registers[NR10];
+        let shadow = self.square1.shadow_frequency;
+        let delta = shadow >> (sweep & 0x07);
+        if sweep & 0x08 != 0 {
+            shadow.wrapping_sub(delta)
+        } else {
+            shadow + delta
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        let sweep = self.registers[NR10];
+        let period = (sweep >> 4) & 0x07;
+        let channel = &mut self.square1;
+        if channel.sweep_timer > 0 {
+            channel.sweep_timer -= 1;
+        }
+        if channel.sweep_timer != 0 {
+            return;
+        }
+        channel.sweep_timer = if period == 0 { 8 } else { period };
+        if !channel.sweep_enabled || period == 0 {
+            return;
+        }
+
+        let frequency = self.sweep_frequency();
+        if frequency > 2047 {
+            self.square1.enabled = false;
+        } else if sweep & 0x07 != 0 {
+            self.square1.shadow_frequency = frequency;
+            self.registers[NR13] = (frequency & 0xFF) as u8;
+            self.registers[NR14] = (self.registers[NR14] & 0xF8) | (frequency >> 8) as u8;
+            if self.sweep_frequency() > 2047 {
+                self.square1.enabled = false;
+            }
+        }
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        let step = self.frame_sequencer_step;
+        if step.is_multiple_of(2) {
+            let registers = &self.registers;
+            self.square1.enabled &= self.square1.length.clock(registers[NR14] & 0x40 != 0);
+            self.square2.enabled &= self.square2.length.clock(registers[NR24] & 0x40 != 0);
+            self.wave.enabled &= self.wave.length.clock(registers[NR34] & 0x40 != 0);
+            self.noise.enabled &= self.noise.length.clock(registers[NR44] & 0x40 != 0);
+        }
+        if step == 2 || step == 6 {
+            self.clock_sweep();
+        }
+        if step == 7 {
+            self.square1.envelope.clock(self.registers[NR12]);
+            self.square2.envelope.clock(self.registers[NR22]);
+            self.noise.envelope.clock(self.registers[NR42]);
+        }
+        self.frame_sequencer_step = (step + 1) % 8;
+    }
+
+    fn tick_wave(&mut self) {
+        let channel = &mut self.wave;
+        if channel.timer > 0 {
+            channel.timer -= 1;
+        }
+        if channel.timer == 0 {
+            let frequency = (self.registers[NR34] as u32 & 0x07) << 8 | self.registers[NR33] as u32;
+            channel.timer = (2048 - frequency) * 2;
+            channel.position = (channel.position + 1) % 32;
+        }
+    }
+
+    fn wave_output(&self) -> u8 {
+        if !self.wave.enabled {
+            return 0;
+        }
+        let byte = self.wave_ram[self.wave.position / 2];
+        let sample = if self.wave.position.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        };
+        match (self.registers[NR32] >> 5) & 0x03 {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            _ => sample >> 2,
+        }
+    }
+
+    fn dac_output(sample: u8, dac_enabled: bool) -> f32 {
+        if dac_enabled {
+            sample as f32 / 7.5 - 1.0
+        } else {
+            0.0
+        }
+    }
+
+    // Each channel's stereo contribution to the final mix, so the four stems
+    // always sum to exactly what is played back.
+    fn channel_outputs(&self) -> [(f32, f32); 4] {
+        let samples = [
+            Apu::dac_output(
+                self.square1.output(self.registers[NR11] >> 6),
+                self.registers[NR12] & 0xF8 != 0,
+            ),
+            Apu::dac_output(
+                self.square2.output(self.registers[NR21] >> 6),
+                self.registers[NR22] & 0xF8 != 0,
+            ),
+            Apu::dac_output(self.wave_output(), self.registers[NR30] & 0x80 != 0),
+            Apu::dac_output(self.noise.output(), self.registers[NR42] & 0xF8 != 0),
+        ];
+
+        let panning = self.registers[NR51];
+        let volume = self.registers[NR50];
+        let left_volume = (((volume >> 4) & 0x07) + 1) as f32 / 8.0 / 4.0;
+        let right_volume = ((volume & 0x07) + 1) as f32 / 8.0 / 4.0;
+
+        let mut outputs = [(0.0, 0.0); 4];
+        for (index, sample) in samples.iter().enumerate() {
+            if panning & (0x10 << index) != 0 {
+                outputs[index].0 = sample * left_volume;
+            }
+            if panning & (0x01 << index) != 0 {
+                outputs[index].1 = sample * right_volume;
+            }
+        }
+        outputs
+    }
+
+    pub fn start_recording(&mut self, path: &Path, mode: RecordingMode) -> io::Result<()> {
+        self.stop_recording()?;
+        let writers = match mode {
+            RecordingMode::Mixed => vec![WavWriter::create(path, SAMPLE_RATE, 2)?],
+            RecordingMode::PerChannel => (1..=4)
+                .map(|channel| WavWriter::create(channel_path(path, channel), SAMPLE_RATE, 2))
+                .collect::<io::Result<Vec<_>>>()?,
+        };
+        self.recording = Some(Recording {
+            mode,
+            writers,
+            error: None,
+        });
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        let Some(mut recording) = self.recording.take() else {
+            return Ok(());
+        };
+        if let Some(error) = recording.error {
+            return Err(error);
+        }
+        for writer in recording.writers.iter_mut() {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    fn record(&mut self, outputs: &[(f32, f32); 4], mixed: (f32, f32)) {
+        let Some(recording) = self.recording.as_mut() else {
+            return;
+        };
+        if recording.error.is_some() {
+            return;
+        }
+        let frames: &[(f32, f32)] = match recording.mode {
+            RecordingMode::Mixed => &[mixed],
+            RecordingMode::PerChannel => outputs,
+        };
+        for (writer, (left, right)) in recording.writers.iter_mut().zip(frames) {
+            if let Err(error) = writer
+                .write_sample(*left)
+                .and_then(|_| writer.write_sample(*right))
+            {
+                recording.error = Some(error);
+                return;
+            }
+        }
+    }
+
+    // The audio buffer and any recording belong to the host and are left as-is.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.registers);
+        writer.write_bytes(&self.wave_ram);
+        self.square1.save_state(writer);
+        self.square2.save_state(writer);
+        self.wave.save_state(writer);
+        self.noise.save_state(writer);
+        writer.write_u32(self.frame_sequencer_timer);
+        writer.write_u8(self.frame_sequencer_step);
+        writer.write_u32(self.sample_timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes(&mut self.registers)?;
+        reader.read_bytes(&mut self.wave_ram)?;
+        self.square1.load_state(reader)?;
+        self.square2.load_state(reader)?;
+        self.wave.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.frame_sequencer_timer = reader.read_u32()?.clamp(1, FRAME_SEQUENCER_PERIOD);
+        self.frame_sequencer_step = reader.read_u8()? % 8;
+        self.sample_timer = reader.read_u32()? % CPU_CLOCK;
+        Ok(())
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            if self.powered() {
+                self.frame_sequencer_timer -= 1;
+                if self.frame_sequencer_timer == 0 {
+                    self.frame_sequencer_timer = FRAME_SEQUENCER_PERIOD;
+                    self.clock_frame_sequencer();
+                }
+                self.square1.tick(self.frequency(NR13, NR14));
+                self.square2.tick(self.frequency(NR23, NR24));
+                self.tick_wave();
+                self.noise.tick(self.registers[NR43]);
+            }
+
+            self.sample_timer += SAMPLE_RATE;
+            if self.sample_timer >= CPU_CLOCK {
+                self.sample_timer -= CPU_CLOCK;
+                let outputs = if self.powered() {
+                    self.channel_outputs()
+                } else {
+                    [(0.0, 0.0); 4]
+                };
+                let mixed = outputs.iter().fold((0.0, 0.0), |(left, right), output| {
+                    (left + output.0, right + output.1)
+                });
+                self.buffer.push(mixed.0, mixed.1);
+                self.record(&outputs, mixed);
+            }
+        }
+    }
+}
+
+/// Path used for a single channel when recording with `RecordingMode::PerChannel`,
+/// e.g. `music.wav` becomes `music.ch1.wav`.
+pub fn channel_path(path: &Path, channel: u8) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.ch{}.wav", stem, channel))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audio_buffer_drops_oldest_when_full() {
+        let mut buffer = AudioBuffer::new(2);
+        buffer.push(0.1, 0.1);
+        buffer.push(0.2, 0.2);
+        buffer.push(0.3, 0.3);
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.pop(), Some((0.2, 0.2)));
+        assert_eq!(buffer.pop(), Some((0.3, 0.3)));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test]
+    fn test_tick_produces_samples_at_sample_rate() {
+        let mut apu = Apu::new();
+        apu.tick(CPU_CLOCK / 64);
+        assert_eq!(apu.buffer().len(), (SAMPLE_RATE / 64) as usize);
+    }
+
+    #[test]
+    fn test_square_channel_is_audible() {
+        let mut apu = Apu::new();
+        apu.write_register(0xFF26, 0x80);
+        apu.write_register(0xFF24, 0x77);
+        apu.write_register(0xFF25, 0x22);
+        apu.write_register(0xFF16, 0x80);
+        apu.write_register(0xFF17, 0xF0);
+        apu.write_register(0xFF18, 0x00);
+        apu.write_register(0xFF19, 0x87);
+        assert_eq!(apu.read_register(0xFF26) & 0x0F, 0x02);
+
+        apu.tick(CPU_CLOCK / 100);
+        let mut levels = Vec::new();
+        while let Some((left, right)) = apu.buffer_mut().pop() {
+            assert_eq!(left, right);
+            levels.push(left);
+        }
+        assert!(levels.iter().any(|level| *level > 0.0));
+        assert!(levels.iter().any(|level| *level < 0.0));
+    }
+
+    #[test]
+    fn test_power_off_clears_registers() {
+        let mut apu = Apu::new();
+        apu.write_register(0xFF26, 0x80);
+        apu.write_register(0xFF12, 0xF3);
+        apu.write_register(0xFF26, 0x00);
+        assert_eq!(apu.read_register(0xFF12), 0x00);
+        assert_eq!(apu.read_register(0xFF26), 0x70);
+
+        apu.write_register(0xFF12, 0xF3);
+        assert_eq!(apu.read_register(0xFF12), 0x00);
+    }
+
+    #[test]
+    fn test_per_channel_recording_writes_one_file_per_channel() {
+        let path = std::env::temp_dir().join("rustyboy_apu_recording_test.wav");
+        let mut apu = Apu::new();
+        apu.write_register(0xFF26, 0x80);
+        apu.start_recording(&path, RecordingMode::PerChannel)
+            .unwrap();
+        apu.tick(CPU_CLOCK / 64);
+        apu.stop_recording().unwrap();
+
+        for channel in 1..=4 {
+            let channel_path = channel_path(&path, channel);
+            let bytes = std::fs::read(&channel_path).unwrap();
+            std::fs::remove_file(&channel_path).unwrap();
+            assert_eq!(bytes.len(), 44 + (SAMPLE_RATE / 64) as usize * 4);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::fmt;
+
+use super::error::ILLEGAL_OPCODES;
+
+const REGISTERS: [&str; 8] = ["B", "C", "D", "E", "H", "L", "[HL]", "A"];
+const REGISTER_PAIRS: [&str; 4] = ["BC", "DE", "HL", "SP"];
+const STACK_PAIRS: [&str; 4] = ["BC", "DE", "HL", "AF"];
+const INDIRECTS: [&str; 4] = ["[BC]", "[DE]", "[HL+]", "[HL-]"];
+const CONDITIONS: [&str; 4] = ["NZ", "Z", "NC", "C"];
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBC", "AND", "XOR", "OR", "CP"];
+const ROTATES: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SWAP", "SRL"];
+const MISC: [&str; 8] = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+
+/// One decoded instruction, with the bytes it was decoded from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Disassembly {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl Disassembly {
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+}
+
+// Listing format: address, raw bytes, then the instruction.
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            f,
+            "{:04X}: {:<8}  {}",
+            self.address,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+/// Total length in bytes of the instruction starting with `opcode`, including
+/// the opcode itself. Illegal opcodes count as a single byte.
+pub fn instruction_length(opcode: u8) -> u8 {
+    match opcode {
+        0xCB => 2,
+        0x08 => 3,
+        0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => 2,
+        0x01 | 0x11 | 0x21 | 0x31 => 3,
+        0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => 2,
+        0xE0 | 0xE8 | 0xF0 | 0xF8 => 2,
+        0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA | 0xEA | 0xFA => 3,
+        0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC => 3,
+        0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => 2,
+        _ => 1,
+    }
+}
+
+/// Decodes the instruction at `address`, reading the opcode and any operand
+/// bytes through `read`.
+pub fn disassemble(read: impl Fn(u16) -> u8, address: u16) -> Disassembly {
+    let opcode = read(address);
+    let length = instruction_length(opcode);
+    let bytes: Vec<u8> = (0..length as u16)
+        .map(|offset| read(address.wrapping_add(offset)))
+        .collect();
+    let text = if opcode == 0xCB {
+        prefixed_text(bytes[1])
+    } else {
+        let operand = Operand::Bytes {
+            bytes: &bytes[1..],
+            address,
+        };
+        text(opcode, &operand)
+    };
+    Disassembly {
+        address,
+        bytes,
+        text,
+    }
+}
+
+/// Decodes instructions from `start` up to, but not including, `end`. The last
+/// instruction may run past `end` if its operands do.
+pub fn disassemble_range(read: impl Fn(u16) -> u8, start: u16, end: u16) -> Vec<Disassembly> {
+    let mut listing = Vec::new();
+    let mut address = start as u32;
+    while address < end as u32 {
+        let instruction = disassemble(&read, address as u16);
+        address += instruction.bytes.len() as u32;
+        listing.push(instruction);
+    }
+    listing
+}
+
+/// The instruction for an opcode with placeholder operands (`n8`, `n16`,
+/// `a16`, `e8`), for when the operand bytes are not at hand.
+pub fn mnemonic(opcode: u8, prefixed: bool) -> String {
+    if prefixed {
+        prefixed_text(opcode)
+    } else {
+        text(opcode, &Operand::Placeholder)
+    }
+}
+
+enum Operand<'a> {
+    Placeholder,
+    Bytes { bytes: &'a [u8], address: u16 },
+}
+
+impl Operand<'_> {
+    fn n8(&self) -> String {
+        match self {
+            Operand::Placeholder => "n8".to_string(),
+            Operand::Bytes { bytes, .. } => format!("${:02X}", bytes[0]),
+        }
+    }
+
+    fn n16(&self, placeholder: &str) -> String {
+        match self {
+            Operand::Placeholder => placeholder.to_string(),
+            Operand::Bytes { bytes, .. } => {
+                format!("${:04X}", u16::from_le_bytes([bytes[0], bytes[1]]))
+            }
+        }
+    }
+
+    fn high_page(&self) -> String {
+        match self {
+            Operand::Placeholder => "a8".to_string(),
+            Operand::Bytes { bytes, .. } => format!("$FF{:02X}", bytes[0]),
+        }
+    }
+
+    // RGBDS takes the jump target rather than the offset for JR.
+    fn jr_target(&self) -> String {
+        match self {
+            Operand::Placeholder => "e8".to_string(),
+            Operand::Bytes { bytes, address } => {
+                let target = address.wrapping_add(2).wrapping_add(bytes[0] as i8 as u16);
+                format!("${:04X}", target)
+            }
+        }
+    }
+
+    fn e8(&self) -> String {
+        match self {
+            Operand::Placeholder => "e8".to_string(),
+            Operand::Bytes { bytes, .. } => (bytes[0] as i8).to_string(),
+        }
+    }
+
+    fn sp_offset(&self) -> String {
+        let offset = self.e8();
+        match offset.strip_prefix('-') {
+            Some(magnitude) => format!("SP - {}", magnitude),
+            None => format!("SP + {}", offset),
+        }
+    }
+}
+
+fn text(opcode: u8, operand: &Operand) -> String {
+    if ILLEGAL_OPCODES.contains(&opcode) {
+        return format!("DB ${:02X}", opcode);
+    }
+
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 7) as usize;
+    let z = opcode & 7;
+    let p = y >> 1;
+    let q = y & 1;
+
+    match (x, z) {
+        (0, 0) => match y {
+            0 => "NOP".to_string(),
+            1 => format!("LD [{}], SP", operand.n16("a16")),
+            2 => "STOP".to_string(),
+            3 => format!("JR {}", operand.jr_target()),
+            _ => format!("JR {}, {}", CONDITIONS[y - 4], operand.jr_target()),
+        },
+        (0, 1) if q == 0 => format!("LD {}, {}", REGISTER_PAIRS[p], operand.n16("n16")),
+        (0, 1) => format!("ADD HL, {}", REGISTER_PAIRS[p]),
+        (0, 2) if q == 0 => format!("LD {}, A", INDIRECTS[p]),
+        (0, 2) => format!("LD A, {}", INDIRECTS[p]),
+        (0, 3) if q == 0 => format!("INC {}", REGISTER_PAIRS[p]),
+        (0, 3) => format!("DEC {}", REGISTER_PAIRS[p]),
+        (0, 4) => format!("INC {}", REGISTERS[y]),
+        (0, 5) => format!("DEC {}", REGISTERS[y]),
+        (0, 6) => format!("LD {}, {}", REGISTERS[y], operand.n8()),
+        (0, _) => MISC[y].to_string(),
+        (1, _) if opcode == 0x76 => "HALT".to_string(),
+        (1, _) => format!("LD {}, {}", REGISTERS[y], REGISTERS[z as usize]),
+        (2, _) => format!("{} A, {}", ALU[y], REGISTERS[z as usize]),
+        (_, 0) => match y {
+            0..=3 => format!("RET {}", CONDITIONS[y]),
+            4 => format!("LDH [{}], A", operand.high_page()),
+            5 => format!("ADD SP, {}", operand.e8()),
+            6 => format!("LDH A, [{}]", operand.high_page()),
+            _ => format!("LD HL, {}", operand.sp_offset()),
+        },
+        (_, 1) if q == 0 => format!("POP {}", STACK_PAIRS[p]),
+        (_, 1) => ["RET", "RETI", "JP HL", "LD SP, HL"][p].to_string(),
+        (_, 2) => match y {
+            0..=3 => format!("JP {}, {}", CONDITIONS[y], operand.n16("a16")),
+            4 => "LDH [C], A".to_string(),
+            5 => format!("LD [{}], A", operand.n16("a16")),
+            6 => "LDH A, [C]".to_string(),
+            _ => format!("LD A, [{}]", operand.n16("a16")),
+        },
+        (_, 3) => match y {
+            0 => format!("JP {}", operand.n16("a16")),
+            6 => "DI".to_string(),
+            _ => "EI".to_string(),
+        },
+        (_, 4) => format!("CALL {}, {}", CONDITIONS[y], operand.n16("a16")),
+        (_, 5) if q == 0 => format!("PUSH {}", STACK_PAIRS[p]),
+        (_, 5) => format!("CALL {}", operand.n16("a16")),
+        (_, 6) => format!("{} A, {}", ALU[y], operand.n8()),
+        _ => format!("RST ${:02X}", y * 8),
+    }
+}
+
+fn prefixed_text(opcode: u8) -> String {
+    let y = ((opcode >> 3) & 7) as usize;
+    let register = REGISTERS[(opcode & 7) as usize];
+    match opcode >> 6 {
+        0 => format!("{} {}", ROTATES[y], register),
+        1 => format!("BIT {}, {}", y, register),
+        2 => format!("RES {}, {}", y, register),
+        _ => format!("SET {}, {}", y, register),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(code: &[u8], start: u16) -> Vec<String> {
+        let read = |address: u16| {
+            code.get(address.wrapping_sub(start) as usize)
+                .copied()
+                .unwrap_or(0)
+        };
+        disassemble_range(read, start, start + code.len() as u16)
+            .iter()
+            .map(|instruction| instruction.text.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_operands_come_from_memory() {
+        let code = [
+            0x2A, 0x20, 0xFE, 0x3E, 0x42, 0xC3, 0x50, 0x01, 0xE0, 0x44, 0xF8, 0xFE, 0xE8, 0x10,
+            0xCB, 0x37, 0xD3, 0x08, 0x00, 0xC0,
+        ];
+        assert_eq!(
+            listing(&code, 0x0150),
+            vec![
+                "LD A, [HL+]",
+                "JR NZ, $0151",
+                "LD A, $42",
+                "JP $0150",
+                "LDH [$FF44], A",
+                "LD HL, SP - 2",
+                "ADD SP, 16",
+                "SWAP A",
+                "DB $D3",
+                "LD [$C000], SP",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lengths_and_listing_format() {
+        assert_eq!(instruction_length(0x00), 1);
+        assert_eq!(instruction_length(0x10), 2);
+        assert_eq!(instruction_length(0xCD), 3);
+        assert_eq!(instruction_length(0xCB), 2);
+
+        let code = [0xCD, 0x34, 0x12];
+        let instruction = disassemble(|address| code[address as usize], 0);
+        assert_eq!(instruction.next_address(), 3);
+        assert_eq!(instruction.to_string(), "0000: CD 34 12  CALL $1234");
+        assert_eq!(mnemonic(0xCD, false), "CALL a16");
+        assert_eq!(mnemonic(0x7E, false), "LD A, [HL]");
+    }
+}
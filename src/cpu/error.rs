@@ -0,0 +1,78 @@
+use std::fmt;
+
+use super::disasm;
+
+/// Opcodes that do not exist on the SM83. Executing one locks up the CPU until
+/// the console is reset.
+pub const ILLEGAL_OPCODES: [u8; 11] = [
+    0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmuError {
+    IllegalOpcode {
+        pc: u16,
+        opcode: u8,
+    },
+    Unimplemented {
+        pc: u16,
+        opcode: u8,
+        prefixed: bool,
+        feature: &'static str,
+    },
+    /// The bus could not carry out an access, such as a ROM bank switch.
+    BusFault {
+        pc: u16,
+        opcode: u8,
+        address: u16,
+    },
+}
+
+impl EmuError {
+    pub fn pc(&self) -> u16 {
+        match self {
+            EmuError::IllegalOpcode { pc, .. }
+            | EmuError::Unimplemented { pc, .. }
+            | EmuError::BusFault { pc, .. } => *pc,
+        }
+    }
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmuError::IllegalOpcode { pc, opcode } => {
+                write!(
+                    f,
+                    "illegal opcode 0x{:02X} at 0x{:04X}, CPU locked",
+                    opcode, pc
+                )
+            }
+            EmuError::Unimplemented {
+                pc,
+                opcode,
+                prefixed,
+                feature,
+            } => write!(
+                f,
+                "unimplemented {} for opcode 0x{}{:02X} ({}) at 0x{:04X}",
+                feature,
+                if *prefixed { "CB" } else { "" },
+                opcode,
+                disasm::mnemonic(*opcode, *prefixed),
+                pc
+            ),
+            EmuError::BusFault {
+                pc,
+                opcode,
+                address,
+            } => write!(
+                f,
+                "bus fault accessing 0x{:04X} from opcode 0x{:02X} at 0x{:04X}",
+                address, opcode, pc
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
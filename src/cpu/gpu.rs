@@ -1,7 +1,56 @@
+use super::state::{StateError, StateReader, StateWriter};
+
 pub const VRAM_BEGIN: usize = 0x8000;
 pub const VRAM_END: usize = 0x9FFF;
 pub const VRAM_SIZE: usize = VRAM_END - VRAM_BEGIN + 1;
 
+pub const OAM_BEGIN: usize = 0xFE00;
+pub const OAM_END: usize = 0xFE9F;
+pub const OAM_SIZE: usize = OAM_END - OAM_BEGIN + 1;
+
+pub const LCD_BEGIN: usize = 0xFF40;
+pub const LCD_END: usize = 0xFF4B;
+pub const DMA_ADDRESS: usize = 0xFF46;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+pub const VBLANK_INTERRUPT: u8 = 0x01;
+pub const STAT_INTERRUPT: u8 = 0x02;
+
+pub const LCDC: usize = 0xFF40;
+const STAT: usize = 0xFF41;
+pub const SCY: usize = 0xFF42;
+pub const SCX: usize = 0xFF43;
+pub const LY: usize = 0xFF44;
+const LYC: usize = 0xFF45;
+pub const BGP: usize = 0xFF47;
+pub const OBP0: usize = 0xFF48;
+pub const OBP1: usize = 0xFF49;
+pub const WY: usize = 0xFF4A;
+pub const WX: usize = 0xFF4B;
+
+const LCDC_ENABLE: u8 = 0x80;
+pub const LCDC_WINDOW_MAP: u8 = 0x40;
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+pub const LCDC_TILE_DATA: u8 = 0x10;
+pub const LCDC_BG_MAP: u8 = 0x08;
+const LCDC_OBJ_SIZE: u8 = 0x04;
+const LCDC_OBJ_ENABLE: u8 = 0x02;
+const LCDC_BG_ENABLE: u8 = 0x01;
+
+const STAT_LYC_INTERRUPT: u8 = 0x40;
+const STAT_OAM_INTERRUPT: u8 = 0x20;
+const STAT_VBLANK_INTERRUPT: u8 = 0x10;
+const STAT_HBLANK_INTERRUPT: u8 = 0x08;
+const STAT_COINCIDENCE: u8 = 0x04;
+
+const OAM_SCAN_CYCLES: u32 = 80;
+const TRANSFER_CYCLES: u32 = 172;
+const LINE_CYCLES: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+pub const SPRITES_PER_LINE: usize = 10;
+
 #[derive(Copy, Clone)]
 enum TilePixelValue {
     Zero,
@@ -15,9 +64,42 @@ fn empty_tile() -> Tile {
     [[TilePixelValue::Zero; 8]; 8]
 }
 
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Transfer = 3,
+}
+
 pub struct Gpu {
     vram: [u8; VRAM_SIZE],
     tile_set: [Tile; 384],
+    oam: [u8; OAM_SIZE],
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    dma: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    line_cycles: u32,
+    window_line: u8,
+    stat_line: bool,
+    frames: u64,
+    framebuffer: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+}
+
+impl Default for Gpu {
+    fn default() -> Gpu {
+        Gpu::new()
+    }
 }
 
 impl Gpu {
@@ -25,6 +107,25 @@ impl Gpu {
         Gpu {
             vram: [0; VRAM_SIZE],
             tile_set: [empty_tile(); 384],
+            oam: [0; OAM_SIZE],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            dma: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            line_cycles: 0,
+            window_line: 0,
+            stat_line: false,
+            frames: 0,
+            framebuffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
         }
     }
 
@@ -61,4 +162,421 @@ impl Gpu {
     pub fn read_vram(&self, address: usize) -> u8 {
         self.vram[address]
     }
+
+    pub fn write_oam(&mut self, index: usize, value: u8) {
+        self.oam[index] = value;
+    }
+
+    pub fn read_oam(&self, index: usize) -> u8 {
+        self.oam[index]
+    }
+
+    pub fn read_register(&self, address: usize) -> u8 {
+        match address {
+            LCDC => self.lcdc,
+            STAT => {
+                let coincidence = if self.ly == self.lyc {
+                    STAT_COINCIDENCE
+                } else {
+                    0
+                };
+                0x80 | self.stat | coincidence | self.mode as u8
+            }
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly,
+            LYC => self.lyc,
+            DMA_ADDRESS => self.dma,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            _ => 0xFF,
+        }
+    }
+
+    // Returns the interrupts raised by the write; only a STAT edge can fire here.
+    pub fn write_register(&mut self, address: usize, value: u8) -> u8 {
+        match address {
+            LCDC => {
+                let was_enabled = self.lcd_enabled();
+                self.lcdc = value;
+                if was_enabled && !self.lcd_enabled() {
+                    self.ly = 0;
+                    self.line_cycles = 0;
+                    self.window_line = 0;
+                    self.mode = Mode::HBlank;
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.mode = Mode::OamScan;
+                }
+            }
+            STAT => self.stat = value & 0x78,
+            SCY => self.scy = value,
+            SCX => self.scx = value,
+            LY => {}
+            LYC => self.lyc = value,
+            DMA_ADDRESS => self.dma = value,
+            BGP => self.bgp = value,
+            OBP0 => self.obp0 = value,
+            OBP1 => self.obp1 = value,
+            WY => self.wy = value,
+            WX => self.wx = value,
+            _ => {}
+        }
+        self.update_stat_line()
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    // The four STAT sources share one interrupt line, which only fires on a
+    // rising edge.
+    fn update_stat_line(&mut self) -> u8 {
+        let line = self.lcd_enabled()
+            && ((self.stat & STAT_LYC_INTERRUPT != 0 && self.ly == self.lyc)
+                || (self.stat & STAT_OAM_INTERRUPT != 0 && self.mode == Mode::OamScan)
+                || (self.stat & STAT_VBLANK_INTERRUPT != 0 && self.mode == Mode::VBlank)
+                || (self.stat & STAT_HBLANK_INTERRUPT != 0 && self.mode == Mode::HBlank));
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        if rising {
+            STAT_INTERRUPT
+        } else {
+            0
+        }
+    }
+
+    // Returns the interrupt flags raised while advancing.
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        if !self.lcd_enabled() {
+            return 0;
+        }
+        let mut interrupts = 0;
+        self.line_cycles += cycles;
+        loop {
+            match self.mode {
+                Mode::OamScan if self.line_cycles >= OAM_SCAN_CYCLES => {
+                    self.mode = Mode::Transfer;
+                }
+                Mode::Transfer if self.line_cycles >= OAM_SCAN_CYCLES + TRANSFER_CYCLES => {
+                    self.render_line();
+                    self.mode = Mode::HBlank;
+                }
+                Mode::HBlank if self.line_cycles >= LINE_CYCLES => {
+                    self.line_cycles -= LINE_CYCLES;
+                    self.ly += 1;
+                    if self.ly as usize == SCREEN_HEIGHT {
+                        self.mode = Mode::VBlank;
+                        self.frames += 1;
+                        interrupts |= VBLANK_INTERRUPT;
+                    } else {
+                        self.mode = Mode::OamScan;
+                    }
+                }
+                Mode::VBlank if self.line_cycles >= LINE_CYCLES => {
+                    self.line_cycles -= LINE_CYCLES;
+                    self.ly += 1;
+                    if self.ly == LINES_PER_FRAME {
+                        self.ly = 0;
+                        self.window_line = 0;
+                        self.mode = Mode::OamScan;
+                    }
+                }
+                _ => break,
+            }
+            interrupts |= self.update_stat_line();
+        }
+        interrupts
+    }
+
+    /// Colour 0-3 of a pixel in one of the 384 decoded tiles.
+    pub fn tile_pixel(&self, tile: usize, row: usize, column: usize) -> u8 {
+        self.tile_set[tile][row][column] as u8
+    }
+
+    // Background and window tile numbers index the tile set either unsigned
+    // from 0x8000 or signed around 0x9000.
+    pub fn bg_tile_index(&self, tile_number: u8) -> usize {
+        if self.lcdc & LCDC_TILE_DATA != 0 {
+            tile_number as usize
+        } else {
+            (256 + tile_number as i8 as i32) as usize
+        }
+    }
+
+    fn map_pixel(&self, map_select: bool, x: usize, y: usize) -> u8 {
+        let map_base = if map_select { 0x1C00 } else { 0x1800 };
+        let tile_number = self.vram[map_base + (y / 8) * 32 + x / 8];
+        self.tile_pixel(self.bg_tile_index(tile_number), y % 8, x % 8)
+    }
+
+    fn render_line(&mut self) {
+        let ly = self.ly as usize;
+        let mut bg_colors = [0u8; SCREEN_WIDTH];
+
+        if self.lcdc & LCDC_BG_ENABLE != 0 {
+            let y = (ly + self.scy as usize) % 256;
+            for (x, color) in bg_colors.iter_mut().enumerate() {
+                let map_x = (x + self.scx as usize) % 256;
+                *color = self.map_pixel(self.lcdc & LCDC_BG_MAP != 0, map_x, y);
+            }
+
+            let window_x = self.wx as usize;
+            if self.lcdc & LCDC_WINDOW_ENABLE != 0 && self.wy as usize <= ly && window_x <= 166 {
+                let y = self.window_line as usize;
+                let start = window_x.saturating_sub(7);
+                for (x, color) in bg_colors.iter_mut().enumerate().skip(start) {
+                    let map_x = x + 7 - window_x;
+                    *color = self.map_pixel(self.lcdc & LCDC_WINDOW_MAP != 0, map_x, y);
+                }
+                self.window_line += 1;
+            }
+        }
+
+        let line = &mut self.framebuffer[ly * SCREEN_WIDTH..(ly + 1) * SCREEN_WIDTH];
+        for (pixel, color) in line.iter_mut().zip(bg_colors) {
+            *pixel = shade(self.bgp, color);
+        }
+
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.render_sprites(ly, &bg_colors);
+        }
+    }
+
+    pub fn sprite_height(&self) -> usize {
+        if self.lcdc & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// OAM indexes of the sprites overlapping line `ly`, in OAM order. Only the
+    /// first `SPRITES_PER_LINE` are drawn.
+    pub fn sprites_on_line(&self, ly: usize) -> impl Iterator<Item = usize> + '_ {
+        let height = self.sprite_height();
+        (0..40).filter(move |sprite| {
+            let top = self.oam[sprite * 4] as usize;
+            ly + 16 >= top && ly + 16 < top + height
+        })
+    }
+
+    fn render_sprites(&mut self, ly: usize, bg_colors: &[u8; SCREEN_WIDTH]) {
+        let height = self.sprite_height();
+        let mut sprites: Vec<usize> = self.sprites_on_line(ly).take(SPRITES_PER_LINE).collect();
+        // Lower X wins, then lower OAM index; draw the winners last.
+        sprites.sort_by_key(|sprite| (self.oam[sprite * 4 + 1], *sprite));
+
+        for &sprite in sprites.iter().rev() {
+            let entry = &self.oam[sprite * 4..sprite * 4 + 4];
+            let (top, left, flags) = (entry[0] as usize, entry[1] as usize, entry[3]);
+            let mut tile = entry[2] as usize;
+            if height == 16 {
+                tile &= 0xFE;
+            }
+
+            let mut row = ly + 16 - top;
+            if flags & 0x40 != 0 {
+                row = height - 1 - row;
+            }
+            let tile = tile + row / 8;
+            let palette = if flags & 0x10 != 0 {
+                self.obp1
+            } else {
+                self.obp0
+            };
+
+            for column in 0..8 {
+                let x = left + column;
+                if !(8..SCREEN_WIDTH + 8).contains(&x) {
+                    continue;
+                }
+                let x = x - 8;
+                let pixel_column = if flags & 0x20 != 0 {
+                    7 - column
+                } else {
+                    column
+                };
+                let color = self.tile_pixel(tile, row % 8, pixel_column);
+                if color == 0 || (flags & 0x80 != 0 && bg_colors[x] != 0) {
+                    continue;
+                }
+                self.framebuffer[ly * SCREEN_WIDTH + x] = shade(palette, color);
+            }
+        }
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.oam);
+        for register in [
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.dma, self.bgp,
+            self.obp0, self.obp1, self.wy, self.wx,
+        ] {
+            writer.write_u8(register);
+        }
+        writer.write_u8(self.mode as u8);
+        writer.write_u32(self.line_cycles);
+        writer.write_u8(self.window_line);
+        writer.write_bool(self.stat_line);
+        writer.write_bytes(&self.framebuffer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut vram = [0; VRAM_SIZE];
+        reader.read_bytes(&mut vram)?;
+        let mut oam = [0; OAM_SIZE];
+        reader.read_bytes(&mut oam)?;
+        let mut registers = [0; 12];
+        for register in registers.iter_mut() {
+            *register = reader.read_u8()?;
+        }
+        let mode = match reader.read_u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Transfer,
+            _ => return Err(StateError::Invalid("PPU mode")),
+        };
+        let line_cycles = reader.read_u32()?;
+        let window_line = reader.read_u8()?;
+        let stat_line = reader.read_bool()?;
+        // Rendering indexes the framebuffer by these, so they are checked
+        // before anything is overwritten.
+        let ly = registers[4];
+        if ly >= LINES_PER_FRAME {
+            return Err(StateError::Invalid("LY"));
+        }
+        if window_line >= LINES_PER_FRAME {
+            return Err(StateError::Invalid("window line"));
+        }
+        if line_cycles > LINE_CYCLES {
+            return Err(StateError::Invalid("line cycles"));
+        }
+
+        for (index, value) in vram.into_iter().enumerate() {
+            self.write_vram(index, value);
+        }
+        self.oam = oam;
+        [
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.dma, self.bgp,
+            self.obp0, self.obp1, self.wy, self.wx,
+        ] = registers;
+        self.mode = mode;
+        self.line_cycles = line_cycles;
+        self.window_line = window_line;
+        self.stat_line = stat_line;
+        reader.read_bytes(&mut self.framebuffer)
+    }
+}
+
+pub fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_tile_row(gpu: &mut Gpu, tile: usize, row: usize, low: u8, high: u8) {
+        gpu.write_vram(tile * 16 + row * 2, low);
+        gpu.write_vram(tile * 16 + row * 2 + 1, high);
+    }
+
+    fn run_frame(gpu: &mut Gpu) -> u8 {
+        let mut interrupts = 0;
+        for _ in 0..(LINE_CYCLES * LINES_PER_FRAME as u32 / 4) {
+            interrupts |= gpu.tick(4);
+        }
+        interrupts
+    }
+
+    #[test]
+    fn test_frame_timing_raises_vblank() {
+        let mut gpu = Gpu::new();
+        gpu.write_register(LCDC, LCDC_ENABLE);
+
+        assert_eq!(gpu.tick(OAM_SCAN_CYCLES), 0);
+        assert_eq!(gpu.mode(), Mode::Transfer);
+        assert_eq!(gpu.tick(LINE_CYCLES - OAM_SCAN_CYCLES), 0);
+        assert_eq!(gpu.read_register(LY), 1);
+
+        let interrupts = run_frame(&mut gpu);
+        assert_eq!(interrupts & VBLANK_INTERRUPT, VBLANK_INTERRUPT);
+        assert_eq!(gpu.frames(), 1);
+        assert_eq!(gpu.read_register(LY), 1);
+    }
+
+    #[test]
+    fn test_load_state_rejects_out_of_range_ly() {
+        let mut gpu = Gpu::new();
+        gpu.write_register(LCDC, LCDC_ENABLE);
+        let mut writer = StateWriter::new();
+        gpu.save_state(&mut writer);
+        let mut bytes = writer.into_bytes();
+        // Magic and version, VRAM, OAM, then LCDC, STAT, SCY and SCX.
+        bytes[5 + VRAM_SIZE + OAM_SIZE + 4] = LINES_PER_FRAME;
+
+        let mut loaded = Gpu::new();
+        let mut reader = StateReader::new(&bytes).unwrap();
+        assert_eq!(
+            loaded.load_state(&mut reader),
+            Err(StateError::Invalid("LY"))
+        );
+        assert_eq!(loaded.read_register(LCDC), 0);
+    }
+
+    #[test]
+    fn test_lyc_match_raises_stat() {
+        let mut gpu = Gpu::new();
+        gpu.write_register(LCDC, LCDC_ENABLE);
+        gpu.write_register(LYC, 2);
+        gpu.write_register(STAT, STAT_LYC_INTERRUPT);
+
+        assert_eq!(gpu.tick(LINE_CYCLES), 0);
+        assert_eq!(gpu.tick(LINE_CYCLES), STAT_INTERRUPT);
+        assert_eq!(gpu.read_register(STAT) & STAT_COINCIDENCE, STAT_COINCIDENCE);
+    }
+
+    #[test]
+    fn test_renders_background_and_sprite() {
+        let mut gpu = Gpu::new();
+        // Tile 1 is solid color 1, tile 2 is solid color 3.
+        for row in 0..8 {
+            write_tile_row(&mut gpu, 1, row, 0xFF, 0x00);
+            write_tile_row(&mut gpu, 2, row, 0xFF, 0xFF);
+        }
+        gpu.write_vram(0x1800, 1);
+        gpu.write_oam(0, 16);
+        gpu.write_oam(1, 16);
+        gpu.write_oam(2, 2);
+        gpu.write_register(BGP, 0xE4);
+        gpu.write_register(OBP0, 0xE4);
+        gpu.write_register(
+            LCDC,
+            LCDC_ENABLE | LCDC_TILE_DATA | LCDC_OBJ_ENABLE | LCDC_BG_ENABLE,
+        );
+        run_frame(&mut gpu);
+
+        let framebuffer = gpu.framebuffer();
+        assert_eq!(framebuffer[0], 1);
+        assert_eq!(framebuffer[7], 1);
+        assert_eq!(framebuffer[8], 3);
+        assert_eq!(framebuffer[16], 0);
+        assert_eq!(framebuffer[8 * SCREEN_WIDTH], 0);
+    }
 }
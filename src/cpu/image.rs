@@ -0,0 +1,27 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use super::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Gray levels for the four DMG shades, lightest first.
+pub const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+pub fn save_grayscale_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(pixels).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+/// Saves a framebuffer of shades 0-3 as a grayscale PNG.
+pub fn save_framebuffer_png(path: &Path, framebuffer: &[u8]) -> io::Result<()> {
+    let pixels: Vec<u8> = framebuffer
+        .iter()
+        .map(|shade| SHADES[*shade as usize & 0x03])
+        .collect();
+    save_grayscale_png(path, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, &pixels)
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 pub enum LoadByteTarget {
     A,
     B,
@@ -88,6 +90,27 @@ pub enum ADDHLTarget {
     SP,
 }
 
+// Base T-cycle counts, not including the extra cycles of taken branches.
+#[rustfmt::skip]
+const INSTRUCTION_CYCLES: [u8; 256] = [
+     4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4,
+     4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4,
+     8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+     8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+     8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  4, 12, 24,  8, 16,
+     8, 12, 12,  4, 12, 16,  8, 16,  8, 16, 12,  4, 12,  4,  8, 16,
+    12, 12,  8,  4,  4, 16,  8, 16, 16,  4, 16,  4,  4,  4,  8, 16,
+    12, 12,  8,  4,  4, 16,  8, 16, 12,  8, 16,  4,  4,  4,  8, 16,
+];
+
 pub enum Instruction {
     ADD(ArithmeticTarget),
     ADDHL(ADDHLTarget),
@@ -107,6 +130,17 @@ pub enum Instruction {
 }
 
 impl Instruction {
+    pub fn cycles(byte: u8, prefixed: bool) -> u8 {
+        if !prefixed {
+            return INSTRUCTION_CYCLES[byte as usize];
+        }
+        match (byte & 0x07, byte) {
+            (6, 0x40..=0x7F) => 12,
+            (6, _) => 16,
+            _ => 8,
+        }
+    }
+
     pub fn from_byte(byte: u8, prefixed: bool) -> Option<Instruction> {
         if prefixed {
             Instruction::from_byte_prefixed(byte)
@@ -498,3 +532,175 @@ impl Instruction {
         }
     }
 }
+
+// Operands that come from the instruction stream are shown with the RGBDS
+// placeholders (n8, n16, a16), since an `Instruction` does not carry them.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::ADD(target) => write!(f, "ADD A, {}", target),
+            Instruction::ADDHL(target) => write!(f, "ADD HL, {}", target),
+            Instruction::JP(JumpTest::Always) => write!(f, "JP a16"),
+            Instruction::JP(test) => write!(f, "JP {}, a16", test),
+            Instruction::CALL(JumpTest::Always) => write!(f, "CALL a16"),
+            Instruction::CALL(test) => write!(f, "CALL {}, a16", test),
+            Instruction::RET(JumpTest::Always) => write!(f, "RET"),
+            Instruction::RET(test) => write!(f, "RET {}", test),
+            Instruction::LD(load) => write!(f, "LD {}", load),
+            Instruction::POP(target) => write!(f, "POP {}", target),
+            Instruction::PUSH(target) => write!(f, "PUSH {}", target),
+            Instruction::NOP => write!(f, "NOP"),
+            Instruction::INC(target) => write!(f, "INC {}", target),
+            Instruction::DEC(target) => write!(f, "DEC {}", target),
+            Instruction::RLCA => write!(f, "RLCA"),
+            Instruction::RRCA => write!(f, "RRCA"),
+            Instruction::RLA => write!(f, "RLA"),
+            Instruction::RRA => write!(f, "RRA"),
+        }
+    }
+}
+
+impl fmt::Display for LoadType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadType::Byte(target, source) => write!(f, "{}, {}", target, source),
+            LoadType::Word(target) => write!(f, "{}, n16", target),
+            LoadType::IndirectFromA(indirect) => write!(f, "{}, A", indirect),
+            LoadType::AFromIndirect(indirect) => write!(f, "A, {}", indirect),
+            LoadType::IndirectFromSP => write!(f, "[a16], SP"),
+        }
+    }
+}
+
+impl fmt::Display for LoadByteTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LoadByteTarget::A => "A",
+            LoadByteTarget::B => "B",
+            LoadByteTarget::C => "C",
+            LoadByteTarget::D => "D",
+            LoadByteTarget::E => "E",
+            LoadByteTarget::H => "H",
+            LoadByteTarget::L => "L",
+            LoadByteTarget::HLI => "[HL]",
+        })
+    }
+}
+
+impl fmt::Display for LoadByteSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LoadByteSource::A => "A",
+            LoadByteSource::B => "B",
+            LoadByteSource::C => "C",
+            LoadByteSource::D => "D",
+            LoadByteSource::E => "E",
+            LoadByteSource::H => "H",
+            LoadByteSource::L => "L",
+            LoadByteSource::D8 => "n8",
+            LoadByteSource::HLI => "[HL]",
+        })
+    }
+}
+
+impl fmt::Display for LoadWordTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LoadWordTarget::BC => "BC",
+            LoadWordTarget::DE => "DE",
+            LoadWordTarget::HL => "HL",
+        })
+    }
+}
+
+impl fmt::Display for Indirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Indirect::BCIndirect => "[BC]",
+            Indirect::DEIndirect => "[DE]",
+            Indirect::HLIndirectPlus => "[HL+]",
+            Indirect::HLIndirectMinus => "[HL-]",
+        })
+    }
+}
+
+impl fmt::Display for IncDecTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            IncDecTarget::A => "A",
+            IncDecTarget::B => "B",
+            IncDecTarget::C => "C",
+            IncDecTarget::D => "D",
+            IncDecTarget::E => "E",
+            IncDecTarget::H => "H",
+            IncDecTarget::L => "L",
+            IncDecTarget::BC => "BC",
+            IncDecTarget::DE => "DE",
+            IncDecTarget::HL => "HL",
+            IncDecTarget::SP => "SP",
+        })
+    }
+}
+
+impl fmt::Display for StackTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            StackTarget::BC => "BC",
+            StackTarget::DE => "DE",
+            StackTarget::HL => "HL",
+            StackTarget::AF => "AF",
+        })
+    }
+}
+
+impl fmt::Display for JumpTest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            JumpTest::NotZero => "NZ",
+            JumpTest::Zero => "Z",
+            JumpTest::NotCarry => "NC",
+            JumpTest::Carry => "C",
+            JumpTest::Always => "",
+        })
+    }
+}
+
+impl fmt::Display for ArithmeticTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ArithmeticTarget::A => "A",
+            ArithmeticTarget::B => "B",
+            ArithmeticTarget::C => "C",
+            ArithmeticTarget::D => "D",
+            ArithmeticTarget::E => "E",
+            ArithmeticTarget::H => "H",
+            ArithmeticTarget::L => "L",
+        })
+    }
+}
+
+impl fmt::Display for ADDHLTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ADDHLTarget::BC => "BC",
+            ADDHLTarget::DE => "DE",
+            ADDHLTarget::HL => "HL",
+            ADDHLTarget::SP => "SP",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_uses_rgbds_syntax() {
+        let text = |byte| Instruction::from_byte(byte, false).unwrap().to_string();
+        assert_eq!(text(0x22), "LD [HL+], A");
+        assert_eq!(text(0x06), "LD B, n8");
+        assert_eq!(text(0x08), "LD [a16], SP");
+        assert_eq!(text(0x80), "ADD A, B");
+        assert_eq!(text(0x09), "ADD HL, BC");
+    }
+}
@@ -0,0 +1,136 @@
+use super::state::{StateError, StateReader, StateWriter};
+
+pub const JOYP_ADDRESS: usize = 0xFF00;
+
+pub const JOYPAD_INTERRUPT: u8 = 0x10;
+
+const SELECT_ACTIONS: u8 = 0x20;
+const SELECT_DIRECTIONS: u8 = 0x10;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // Returns whether the button is on the direction row and its bit in the
+    // low nibble of JOYP.
+    fn line(self) -> (bool, u8) {
+        match self {
+            Button::Right => (true, 0x01),
+            Button::Left => (true, 0x02),
+            Button::Up => (true, 0x04),
+            Button::Down => (true, 0x08),
+            Button::A => (false, 0x01),
+            Button::B => (false, 0x02),
+            Button::Select => (false, 0x04),
+            Button::Start => (false, 0x08),
+        }
+    }
+}
+
+/// The button matrix behind JOYP. Pressed buttons read as 0 on whichever rows
+/// the game has selected by clearing bit 4 or 5.
+pub struct Joypad {
+    select: u8,
+    directions: u8,
+    actions: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: SELECT_ACTIONS | SELECT_DIRECTIONS,
+            directions: 0,
+            actions: 0,
+        }
+    }
+
+    fn pressed_lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            lines |= self.directions;
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            lines |= self.actions;
+        }
+        lines
+    }
+
+    pub fn read_register(&self) -> u8 {
+        0xC0 | self.select | (!self.pressed_lines() & 0x0F)
+    }
+
+    pub fn write_register(&mut self, value: u8) {
+        self.select = value & (SELECT_ACTIONS | SELECT_DIRECTIONS);
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.select);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.select = reader.read_u8()? & (SELECT_ACTIONS | SELECT_DIRECTIONS);
+        Ok(())
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        let (direction, bit) = button.line();
+        let row = if direction {
+            self.directions
+        } else {
+            self.actions
+        };
+        row & bit != 0
+    }
+
+    // Returns true when a selected line went low and the joypad interrupt
+    // should fire.
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let before = self.pressed_lines();
+        let (direction, bit) = button.line();
+        let row = if direction {
+            &mut self.directions
+        } else {
+            &mut self.actions
+        };
+        if pressed {
+            *row |= bit;
+        } else {
+            *row &= !bit;
+        }
+        self.pressed_lines() & !before != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selected_row_reads_pressed_buttons() {
+        let mut joypad = Joypad::new();
+        assert!(!joypad.set_button(Button::Start, true));
+        assert_eq!(joypad.read_register(), 0xFF);
+
+        joypad.write_register(0x10);
+        assert_eq!(joypad.read_register(), 0xD7);
+        joypad.write_register(0x20);
+        assert_eq!(joypad.read_register(), 0xEF);
+        assert!(joypad.set_button(Button::Down, true));
+        assert_eq!(joypad.read_register(), 0xE7);
+    }
+}
@@ -0,0 +1,271 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::serial::SerialSink;
+
+// Each message on the wire is a frame kind followed by the data byte.
+const FRAME_TRANSFER: u8 = 0x01;
+const FRAME_REPLY: u8 = 0x02;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+/// A serial sink that exchanges bytes with another emulator over a local socket.
+///
+/// Addresses are either `host:port` for TCP or `unix:PATH` for a Unix socket.
+/// Whichever side uses the internal clock sends its byte when the transfer
+/// starts and collects the partner's byte once the eight bit-times are up,
+/// without blocking: the transfer stays pending until the reply arrives or the
+/// timeout passes. The externally clocked side answers when it polls, so both
+/// ends can also be stepped alternately on one thread.
+pub struct LinkCable {
+    stream: Box<dyn Stream>,
+    received: Vec<u8>,
+    incoming: Option<u8>,
+    reply: Option<u8>,
+    outgoing: Option<u8>,
+    deadline: Option<Instant>,
+    connected: bool,
+    error: Option<String>,
+    timeout: Duration,
+}
+
+impl LinkCable {
+    fn new(stream: Box<dyn Stream>) -> LinkCable {
+        LinkCable {
+            stream,
+            received: Vec::new(),
+            incoming: None,
+            reply: None,
+            outgoing: None,
+            deadline: None,
+            connected: true,
+            error: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    fn from_tcp(stream: TcpStream) -> io::Result<LinkCable> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(LinkCable::new(Box::new(stream)))
+    }
+
+    #[cfg(unix)]
+    fn from_unix(stream: std::os::unix::net::UnixStream) -> io::Result<LinkCable> {
+        stream.set_nonblocking(true)?;
+        Ok(LinkCable::new(Box::new(stream)))
+    }
+
+    /// Waits for a single partner to connect.
+    pub fn listen(address: &str) -> io::Result<LinkCable> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            return LinkCable::listen_unix(path);
+        }
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        LinkCable::from_tcp(stream)
+    }
+
+    pub fn connect(address: &str) -> io::Result<LinkCable> {
+        if let Some(path) = address.strip_prefix("unix:") {
+            return LinkCable::connect_unix(path);
+        }
+        LinkCable::from_tcp(TcpStream::connect(address)?)
+    }
+
+    #[cfg(unix)]
+    fn listen_unix(path: &str) -> io::Result<LinkCable> {
+        let _ = std::fs::remove_file(path);
+        let listener = std::os::unix::net::UnixListener::bind(path)?;
+        let (stream, _) = listener.accept()?;
+        LinkCable::from_unix(stream)
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(path: &str) -> io::Result<LinkCable> {
+        LinkCable::from_unix(std::os::unix::net::UnixStream::connect(path)?)
+    }
+
+    #[cfg(not(unix))]
+    fn listen_unix(_path: &str) -> io::Result<LinkCable> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "unix sockets are not available",
+        ))
+    }
+
+    #[cfg(not(unix))]
+    fn connect_unix(_path: &str) -> io::Result<LinkCable> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "unix sockets are not available",
+        ))
+    }
+
+    /// Two connected ends, for linking two machines inside one process.
+    pub fn pair() -> io::Result<(LinkCable, LinkCable)> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        Ok((LinkCable::from_tcp(server)?, LinkCable::from_tcp(client)?))
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    fn disconnect(&mut self, error: io::Error) {
+        if self.connected {
+            self.error = Some(format!("link cable disconnected: {}", error));
+        }
+        self.connected = false;
+    }
+
+    fn send(&mut self, kind: u8, byte: u8) {
+        if !self.connected {
+            return;
+        }
+        if let Err(error) = write_frame(&mut self.stream, kind, byte) {
+            self.disconnect(error);
+        }
+    }
+
+    fn poll(&mut self) {
+        let mut chunk = [0; 64];
+        while self.connected {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.disconnect(io::Error::from(ErrorKind::UnexpectedEof)),
+                Ok(count) => self.received.extend_from_slice(&chunk[..count]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => self.disconnect(error),
+            }
+        }
+
+        while self.received.len() >= 2 {
+            let (kind, byte) = (self.received[0], self.received[1]);
+            self.received.drain(..2);
+            match kind {
+                FRAME_TRANSFER => {
+                    if let Some(outgoing) = self.outgoing {
+                        // Both sides are driving the clock; treat it as a swap.
+                        self.send(FRAME_REPLY, outgoing);
+                        self.reply = Some(byte);
+                    } else {
+                        self.incoming = Some(byte);
+                    }
+                }
+                // A reply with no transfer in flight is left over from a
+                // clock collision and can be dropped.
+                FRAME_REPLY if self.outgoing.is_some() => self.reply = Some(byte),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn write_frame(stream: &mut dyn Stream, kind: u8, byte: u8) -> io::Result<()> {
+    let frame = [kind, byte];
+    let mut written = 0;
+    while written < frame.len() {
+        match stream.write(&frame[written..]) {
+            Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero)),
+            Ok(count) => written += count,
+            Err(error) if error.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
+    }
+    stream.flush()
+}
+
+impl SerialSink for LinkCable {
+    fn start(&mut self, byte: u8) {
+        self.outgoing = Some(byte);
+        self.reply = None;
+        self.deadline = Some(Instant::now() + self.timeout);
+        self.send(FRAME_TRANSFER, byte);
+    }
+
+    fn transfer(&mut self, _byte: u8) -> Option<u8> {
+        self.poll();
+        let timed_out = self
+            .deadline
+            .is_none_or(|deadline| Instant::now() >= deadline);
+        if self.reply.is_none() && self.connected && !timed_out {
+            return None;
+        }
+        self.outgoing = None;
+        self.deadline = None;
+        Some(self.reply.take().unwrap_or(0xFF))
+    }
+
+    fn receive(&mut self, outgoing: Option<u8>) -> Option<u8> {
+        self.poll();
+        let incoming = self.incoming.take()?;
+        // An unarmed port still answers so the partner is not left waiting,
+        // but the byte it sent is lost.
+        self.send(FRAME_REPLY, outgoing.unwrap_or(0xFF));
+        outgoing.map(|_| incoming)
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::serial::*;
+    use super::*;
+
+    #[test]
+    fn test_internal_and_external_clock_exchange_bytes() {
+        let (master_end, slave_end) = LinkCable::pair().unwrap();
+        let mut master = Serial::new();
+        let mut slave = Serial::new();
+        master.set_sink(Box::new(master_end));
+        slave.set_sink(Box::new(slave_end));
+
+        slave.write_register(SB_ADDRESS, 0x22);
+        slave.write_register(SC_ADDRESS, 0x80);
+        master.write_register(SB_ADDRESS, 0x11);
+        master.write_register(SC_ADDRESS, 0x81);
+
+        let mut master_done = false;
+        let mut slave_done = false;
+        for _ in 0..64 {
+            master_done |= master.tick(256);
+            slave_done |= slave.tick(256);
+        }
+        assert!(master_done && slave_done);
+        assert_eq!(master.read_register(SB_ADDRESS), 0x22);
+        assert_eq!(slave.read_register(SB_ADDRESS), 0x11);
+        assert_eq!(slave.read_register(SC_ADDRESS), 0x7E);
+    }
+
+    #[test]
+    fn test_disconnected_partner_reads_as_ones() {
+        let (mut master_end, slave_end) = LinkCable::pair().unwrap();
+        drop(slave_end);
+        master_end.start(0x11);
+        let reply = (0..1000).find_map(|_| {
+            thread::sleep(POLL_INTERVAL);
+            master_end.transfer(0x11)
+        });
+        assert_eq!(reply, Some(0xFF));
+        assert!(master_end
+            .take_error()
+            .unwrap()
+            .starts_with("link cable disconnected"));
+    }
+}
@@ -1,34 +1,297 @@
+pub mod apu;
+pub mod disasm;
+pub mod error;
 pub mod gpu;
+pub mod image;
 pub mod instructions;
+pub mod joypad;
+pub mod link;
+pub mod model;
+pub mod printer;
 pub mod registers;
+pub mod serial;
+pub mod state;
+pub mod wav;
 
+use self::apu::*;
+use self::error::*;
 use self::gpu::*;
 use self::instructions::*;
+use self::joypad::*;
+use self::model::*;
 use self::registers::Registers;
+use self::serial::*;
+use self::state::*;
+use crate::symbols::{self, Symbols};
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::path::Path;
 
-struct MemoryBus {
-    memory: [u8; 0xFFFF],
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+const INTERRUPT_FLAG_ADDRESS: usize = 0xFF0F;
+const SERIAL_INTERRUPT: u8 = 0x08;
+const STUB_LY: u8 = 0x90;
+const HEADER_CHECKSUM_ADDRESS: u16 = 0x014D;
+const CARTRIDGE_TYPE_ADDRESS: usize = 0x0147;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BusAccess {
+    pub address: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub address: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn matches(&self, address: u16, kind: AccessKind) -> bool {
+        self.address == address
+            && match self.kind {
+                WatchKind::Read => kind == AccessKind::Read,
+                WatchKind::Write => kind == AccessKind::Write,
+                WatchKind::Access => true,
+            }
+    }
+}
+
+pub struct MemoryBus {
+    memory: [u8; 0x10000],
     gpu: Gpu,
+    apu: Apu,
+    serial: Serial,
+    joypad: Joypad,
+    // With flat memory every address is plain RAM and no device is attached,
+    // which is what CPU conformance vectors expect.
+    flat: bool,
+    // Gameboy Doctor logs are taken with LY stuck at 0x90, the first VBlank
+    // line, so that games waiting for VBlank run the same as in the reference.
+    stub_ly: bool,
+    // Checked before touching `trace`, so the bus pays nothing for tracing
+    // while it is off.
+    tracing: bool,
+    trace: RefCell<Vec<Option<BusAccess>>>,
+    watchpoints: Vec<Watchpoint>,
+    // The first access to hit a watchpoint since the last `take_watch_hit`.
+    watch_hit: Cell<Option<BusAccess>>,
+    // One more than the frame each address was last written in, or 0 if it
+    // has not been, while write tracking is on.
+    write_frames: Option<Vec<u64>>,
+    // The address of the first write since the last step that the bus could
+    // not carry out.
+    fault: Option<u16>,
 }
 
 impl MemoryBus {
-    fn read_byte(&self, address: u16) -> u8 {
+    pub fn read_byte(&self, address: u16) -> u8 {
+        let value = self.read_device(address);
+        self.record(address, value, AccessKind::Read);
+        value
+    }
+
+    pub fn write_byte(&mut self, address: u16, value: u8) {
+        self.record(address, value, AccessKind::Write);
+        if let Some(frames) = self.write_frames.as_mut() {
+            frames[address as usize] = self.gpu.frames() + 1;
+        }
+        if !self.flat && address < 0x8000 {
+            self.write_rom(address, value);
+            return;
+        }
+        self.write_device(address, value);
+    }
+
+    // ROM cannot be written; writes go to the cartridge's mapper, if it has
+    // one. Mappers switch ROM banks through 0x2000-0x3FFF, but only a plain
+    // 32 KiB ROM is mapped, so switching to any bank but the first
+    // switchable one cannot be served.
+    fn write_rom(&mut self, address: u16, value: u8) {
+        if self.memory[CARTRIDGE_TYPE_ADDRESS] != 0
+            && (0x2000..=0x3FFF).contains(&address)
+            && value > 1
+        {
+            self.fault.get_or_insert(address);
+        }
+    }
+
+    fn record(&self, address: u16, value: u8, kind: AccessKind) {
+        let access = BusAccess {
+            address,
+            value,
+            kind,
+        };
+        if self.tracing {
+            self.trace.borrow_mut().push(Some(access));
+        }
+        if self.watch_hit.get().is_none()
+            && self
+                .watchpoints
+                .iter()
+                .any(|watchpoint| watchpoint.matches(address, kind))
+        {
+            self.watch_hit.set(Some(access));
+        }
+    }
+
+    // An M-cycle in which the CPU is busy internally and leaves the bus idle.
+    fn idle(&self) {
+        if self.tracing {
+            self.trace.borrow_mut().push(None);
+        }
+    }
+
+    /// Reads like the CPU would, but without tracing or triggering
+    /// watchpoints, for debuggers and viewers.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.read_device(address)
+    }
+
+    pub fn poke(&mut self, address: u16, value: u8) {
+        self.write_device(address, value);
+    }
+
+    /// Starts or stops remembering which frame the CPU last wrote each address
+    /// in, for viewers that highlight recent writes.
+    pub fn set_write_tracking(&mut self, enabled: bool) {
+        match (enabled, self.write_frames.is_some()) {
+            (true, false) => self.write_frames = Some(vec![0; 0x10000]),
+            (false, true) => self.write_frames = None,
+            _ => {}
+        }
+    }
+
+    pub fn last_write_frame(&self, address: u16) -> Option<u64> {
+        let frame = self.write_frames.as_ref()?[address as usize];
+        frame.checked_sub(1)
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoints(&mut self, address: u16) {
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.address != address);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn take_watch_hit(&self) -> Option<BusAccess> {
+        self.watch_hit.take()
+    }
+
+    /// Starts recording every CPU read and write, discarding any earlier trace.
+    pub fn start_trace(&mut self) {
+        self.tracing = true;
+        self.trace.get_mut().clear();
+    }
+
+    /// Stops recording and returns what the CPU did on the bus since
+    /// `start_trace`, one entry per M-cycle with `None` for idle cycles.
+    pub fn take_trace(&mut self) -> Vec<Option<BusAccess>> {
+        self.tracing = false;
+        std::mem::take(self.trace.get_mut())
+    }
+
+    pub fn set_flat(&mut self, flat: bool) {
+        self.flat = flat;
+    }
+
+    pub fn set_ly_stub(&mut self, stub: bool) {
+        self.stub_ly = stub;
+    }
+
+    fn read_device(&self, address: u16) -> u8 {
         let address = address as usize;
+        if self.flat {
+            return self.memory[address];
+        }
         match address {
             gpu::VRAM_BEGIN..=gpu::VRAM_END => self.gpu.read_vram(address - gpu::VRAM_BEGIN),
+            gpu::OAM_BEGIN..=gpu::OAM_END => self.gpu.read_oam(address - gpu::OAM_BEGIN),
+            joypad::JOYP_ADDRESS => self.joypad.read_register(),
+            serial::SB_ADDRESS..=serial::SC_ADDRESS => self.serial.read_register(address),
+            apu::APU_BEGIN..=apu::APU_END => self.apu.read_register(address),
+            gpu::LY if self.stub_ly => STUB_LY,
+            gpu::LCD_BEGIN..=gpu::LCD_END => self.gpu.read_register(address),
             _ => self.memory[address],
         }
     }
 
-    fn write_byte(&mut self, address: u16, value: u8) {
+    fn write_device(&mut self, address: u16, value: u8) {
         let address = address as usize;
+        if self.flat {
+            self.memory[address] = value;
+            return;
+        }
         match address {
             gpu::VRAM_BEGIN..=gpu::VRAM_END => {
                 self.gpu.write_vram(address - gpu::VRAM_BEGIN, value)
             }
+            gpu::OAM_BEGIN..=gpu::OAM_END => self.gpu.write_oam(address - gpu::OAM_BEGIN, value),
+            joypad::JOYP_ADDRESS => self.joypad.write_register(value),
+            serial::SB_ADDRESS..=serial::SC_ADDRESS => self.serial.write_register(address, value),
+            apu::APU_BEGIN..=apu::APU_END => self.apu.write_register(address, value),
+            gpu::DMA_ADDRESS => {
+                self.gpu.write_register(address, value);
+                self.oam_dma(value);
+            }
+            gpu::LCD_BEGIN..=gpu::LCD_END => {
+                let interrupts = self.gpu.write_register(address, value);
+                self.memory[INTERRUPT_FLAG_ADDRESS] |= interrupts;
+            }
             _ => self.memory[address] = value,
         }
     }
+
+    // Copies a page into OAM in one go rather than over 160 cycles.
+    fn oam_dma(&mut self, page: u8) {
+        let source = (page as u16) << 8;
+        for index in 0..gpu::OAM_SIZE as u16 {
+            let value = self.read_device(source + index);
+            self.gpu.write_oam(index as usize, value);
+        }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        if self.flat {
+            return;
+        }
+        self.apu.tick(cycles as u32);
+        if self.serial.tick(cycles as u32) {
+            self.memory[INTERRUPT_FLAG_ADDRESS] |= SERIAL_INTERRUPT;
+        }
+        self.memory[INTERRUPT_FLAG_ADDRESS] |= self.gpu.tick(cycles as u32);
+    }
+
+    pub fn gpu(&self) -> &Gpu {
+        &self.gpu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
 }
 
 pub struct Cpu {
@@ -37,27 +300,79 @@ pub struct Cpu {
     sp: u16,
     bus: MemoryBus,
     is_halted: bool,
+    is_locked: bool,
+    frame_cycles: u32,
+    trace_log: Option<Box<dyn io::Write + Send>>,
+    trace_symbols: Option<Symbols>,
+    // Every section of a save state has a fixed size, measured once.
+    state_size: usize,
+}
+
+/// What a successful `Cpu::step` executed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StepInfo {
+    pub pc: u16,
+    pub opcode: u8,
+    pub prefixed: bool,
+    pub cycles: u8,
 }
 
 impl Cpu {
+    /// A DMG. See `with_model`.
     pub fn new(boot_rom: Option<Vec<u8>>, game_rom: Vec<u8>) -> Cpu {
-        Cpu {
+        Cpu::with_model(Model::default(), boot_rom, game_rom)
+    }
+
+    /// Without a boot ROM the machine starts at 0x0100 in the state `model`'s
+    /// boot ROM would have left it in.
+    pub fn with_model(model: Model, boot_rom: Option<Vec<u8>>, game_rom: Vec<u8>) -> Cpu {
+        let skip_boot = boot_rom.is_none();
+        let mut memory = [0; 0x10000];
+        let rom_size = game_rom.len().min(0x8000);
+        memory[..rom_size].copy_from_slice(&game_rom[..rom_size]);
+        if let Some(boot_rom) = boot_rom {
+            let boot_size = boot_rom.len().min(0x100);
+            memory[..boot_size].copy_from_slice(&boot_rom[..boot_size]);
+        }
+
+        let mut cpu = Cpu {
             registers: Registers::new(),
             pc: 0,
             sp: 0,
             bus: MemoryBus {
-                memory: [0; 0xFFFF],
+                memory,
                 gpu: Gpu::new(),
+                apu: Apu::new(),
+                serial: Serial::new(),
+                joypad: Joypad::new(),
+                flat: false,
+                stub_ly: false,
+                tracing: false,
+                trace: RefCell::new(Vec::new()),
+                watchpoints: Vec::new(),
+                watch_hit: Cell::new(None),
+                write_frames: None,
+                fault: None,
             },
             is_halted: false,
+            is_locked: false,
+            frame_cycles: 0,
+            trace_log: None,
+            trace_symbols: None,
+            state_size: 0,
+        };
+        cpu.state_size = cpu.save_state().len();
+        if skip_boot {
+            cpu.skip_boot_rom(model);
         }
+        cpu
     }
 
-    fn execute(&mut self, instruction: Instruction) -> u16 {
+    fn execute(&mut self, instruction: Instruction) -> Result<u16, EmuError> {
         if self.is_halted {
-            return self.pc;
+            return Ok(self.pc);
         }
-        match instruction {
+        let next_pc = match instruction {
             Instruction::ADD(target) => match target {
                 ArithmeticTarget::A => {
                     let value = self.registers.a;
@@ -142,7 +457,7 @@ impl Cpu {
                         _ => self.pc.wrapping_add(1),
                     }
                 }
-                _ => panic!("TODO: implement more load types"),
+                _ => return Err(self.unimplemented("load type")),
             },
             Instruction::POP(target) => {
                 let result = self.pop();
@@ -182,6 +497,10 @@ impl Cpu {
                     JumpTest::NotCarry => !self.registers.f.carry,
                     JumpTest::Always => true,
                 };
+                // Checking the condition takes a cycle of its own.
+                if !matches!(test, JumpTest::Always) {
+                    self.bus.idle();
+                }
                 self.return_(jump_condition)
             }
             Instruction::NOP => self.pc.wrapping_add(1),
@@ -279,16 +598,19 @@ impl Cpu {
                     self.pc.wrapping_add(1)
                 }
                 IncDecTarget::BC => {
+                    self.bus.idle();
                     let value = self.registers.get_bc();
                     self.registers.set_bc(value.wrapping_add(1));
                     self.pc.wrapping_add(2)
                 }
                 IncDecTarget::DE => {
+                    self.bus.idle();
                     let value = self.registers.get_de();
                     self.registers.set_de(value.wrapping_add(1));
                     self.pc.wrapping_add(2)
                 }
                 IncDecTarget::SP => {
+                    self.bus.idle();
                     let value = self.sp;
                     self.sp = value.wrapping_add(1);
                     self.pc.wrapping_add(2)
@@ -365,16 +687,19 @@ impl Cpu {
                     self.pc.wrapping_add(1)
                 }
                 IncDecTarget::BC => {
+                    self.bus.idle();
                     self.registers
                         .set_bc(self.registers.get_bc().wrapping_sub(1));
                     self.pc.wrapping_add(2)
                 }
                 IncDecTarget::DE => {
+                    self.bus.idle();
                     self.registers
                         .set_de(self.registers.get_de().wrapping_sub(1));
                     self.pc.wrapping_add(2)
                 }
                 IncDecTarget::SP => {
+                    self.bus.idle();
                     self.sp = self.sp.wrapping_sub(1);
                     self.pc.wrapping_add(2)
                 }
@@ -388,25 +713,38 @@ impl Cpu {
                     self.pc.wrapping_add(3)
                 }
             },
-            Instruction::RLA => {
-                panic!("TODO: implement RLA");
-            }
-            Instruction::RLCA => {
-                panic!("TODO: implement RLCA");
-            }
-            Instruction::RRA => {
-                panic!("TODO: implement RRA");
-            }
-            Instruction::RRCA => {
-                panic!("TODO: implement RRA");
-            }
+            Instruction::RLA => return Err(self.unimplemented("RLA")),
+            Instruction::RLCA => return Err(self.unimplemented("RLCA")),
+            Instruction::RRA => return Err(self.unimplemented("RRA")),
+            Instruction::RRCA => return Err(self.unimplemented("RRCA")),
+        };
+        Ok(next_pc)
+    }
+
+    fn current_opcode(&self) -> (u8, bool) {
+        let opcode = self.bus.read_byte(self.pc);
+        if opcode == 0xCB {
+            (self.bus.read_byte(self.pc.wrapping_add(1)), true)
+        } else {
+            (opcode, false)
+        }
+    }
+
+    fn unimplemented(&self, feature: &'static str) -> EmuError {
+        let (opcode, prefixed) = self.current_opcode();
+        EmuError::Unimplemented {
+            pc: self.pc,
+            opcode,
+            prefixed,
+            feature,
         }
     }
 
     fn jump(&self, should_jump: bool) -> u16 {
         if should_jump {
-            let least_significant_byte = self.bus.read_byte(self.pc + 1) as u16;
-            let most_significant_byte = self.bus.read_byte(self.pc + 2) as u16;
+            let least_significant_byte = self.bus.read_byte(self.pc.wrapping_add(1)) as u16;
+            let most_significant_byte = self.bus.read_byte(self.pc.wrapping_add(2)) as u16;
+            self.bus.idle();
             (most_significant_byte << 8) | least_significant_byte
         } else {
             self.pc.wrapping_add(3)
@@ -423,6 +761,7 @@ impl Cpu {
     }
 
     fn add_hl(&mut self, value: u16) {
+        self.bus.idle();
         let (new_value, did_overflow) = self.registers.get_hl().overflowing_add(value);
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
@@ -440,6 +779,7 @@ impl Cpu {
     }
 
     fn push(&mut self, value: u16) {
+        self.bus.idle();
         self.sp = self.sp.wrapping_sub(1);
         self.bus.write_byte(self.sp, ((value & 0xFF00) >> 8) as u8);
 
@@ -447,26 +787,287 @@ impl Cpu {
         self.bus.write_byte(self.sp, (value & 0xFF) as u8);
     }
 
-    pub fn step(&mut self) {
-        let mut instruction_byte = self.bus.read_byte(self.pc);
-        let prefixed = instruction_byte == 0xCB;
-        if prefixed {
-            instruction_byte = self.bus.read_byte(self.pc + 1);
+    pub fn audio_buffer_mut(&mut self) -> &mut AudioBuffer {
+        self.bus.apu.buffer_mut()
+    }
+
+    pub fn start_audio_recording(
+        &mut self,
+        path: impl AsRef<Path>,
+        mode: RecordingMode,
+    ) -> io::Result<()> {
+        self.bus.apu.start_recording(path.as_ref(), mode)
+    }
+
+    pub fn stop_audio_recording(&mut self) -> io::Result<()> {
+        self.bus.apu.stop_recording()
+    }
+
+    pub fn set_serial_sink(&mut self, sink: Box<dyn SerialSink>) {
+        self.bus.serial.set_sink(sink);
+    }
+
+    /// See [`SerialSink::take_error`].
+    pub fn take_serial_error(&mut self) -> Option<String> {
+        self.bus.serial.take_sink_error()
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.is_locked
+    }
+
+    pub fn bus(&self) -> &MemoryBus {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut MemoryBus {
+        &mut self.bus
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp;
+    }
+
+    /// Puts the machine in the state `model`'s boot ROM leaves it in, ready to
+    /// start the cartridge at 0x0100.
+    pub fn skip_boot_rom(&mut self, model: Model) {
+        let header_checksum = self.bus.read_device(HEADER_CHECKSUM_ADDRESS);
+        let registers = model.post_boot_registers(header_checksum);
+        self.registers.set_af(registers.af);
+        self.registers.set_bc(registers.bc);
+        self.registers.set_de(registers.de);
+        self.registers.set_hl(registers.hl);
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+
+        for &(address, value) in model.post_boot_io() {
+            match address as usize {
+                // Only the register; the boot ROM never starts a transfer.
+                DMA_ADDRESS => {
+                    self.bus.gpu.write_register(DMA_ADDRESS, value);
+                }
+                // The channel bits are read-only, so the chime has to be
+                // ended on the APU itself.
+                NR52_ADDRESS => {
+                    self.bus.write_device(address, value);
+                    if value & 0x01 != 0 {
+                        self.bus.apu.end_boot_chime();
+                    }
+                }
+                _ => self.bus.write_device(address, value),
+            }
         }
+        self.bus
+            .write_device(model::DIV_ADDRESS, model.post_boot_div());
+    }
 
-        let next_pc = if let Some(instruction) = Instruction::from_byte(instruction_byte, prefixed)
-        {
-            self.execute(instruction)
-        } else {
-            let description = format!(
-                "0x{}{:x}",
-                if prefixed { "cb" } else { "" },
-                instruction_byte
-            );
-            panic!("Unknown instruction found for: {}", description);
+    /// Writes a Gameboy Doctor line to `log` before every instruction, or
+    /// stops logging when given `None`.
+    pub fn set_trace_log(&mut self, log: Option<Box<dyn io::Write + Send>>) {
+        self.trace_log = log;
+    }
+
+    /// Follows each trace line with ` ; ` and the symbol nearest its pc, or
+    /// stops when given `None`. Off by default, as it makes the lines no
+    /// longer match Gameboy Doctor logs.
+    pub fn set_trace_symbols(&mut self, symbols: Option<Symbols>) {
+        self.trace_symbols = symbols;
+    }
+
+    fn write_trace_line(&mut self) {
+        let Some(log) = self.trace_log.as_mut() else {
+            return;
+        };
+        let registers = &self.registers;
+        let pcmem: Vec<String> = (0..4)
+            .map(|offset| format!("{:02X}", self.bus.read_device(self.pc.wrapping_add(offset))))
+            .collect();
+        let symbol = self
+            .trace_symbols
+            .as_ref()
+            .and_then(|symbols| symbols.describe(symbols::mapped_bank(self.pc), self.pc))
+            .map_or(String::new(), |name| format!(" ; {}", name));
+        let line = writeln!(
+            log,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}{}",
+            registers.a,
+            u8::from(registers.f),
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+            self.sp,
+            self.pc,
+            pcmem.join(","),
+            symbol
+        );
+        // A log that can no longer be written is dropped rather than stopping
+        // emulation.
+        if line.is_err() {
+            self.trace_log = None;
+        }
+    }
+
+    // Reads go around the bus trace so that disassembling for a log or a
+    // debugger does not show up as CPU accesses.
+    pub fn disassemble(&self, address: u16) -> disasm::Disassembly {
+        disasm::disassemble(|address| self.bus.peek(address), address)
+    }
+
+    /// The last completed picture, `SCREEN_WIDTH` by `SCREEN_HEIGHT` shades
+    /// from 0 (lightest) to 3 (darkest).
+    pub fn framebuffer(&self) -> &[u8] {
+        self.bus.gpu.framebuffer()
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.bus.joypad.set_button(button, pressed) {
+            self.bus.memory[INTERRUPT_FLAG_ADDRESS] |= JOYPAD_INTERRUPT;
+        }
+    }
+
+    /// Runs until a frame's worth of cycles has elapsed. On error the partial
+    /// frame is kept, so calling again picks up where it stopped.
+    pub fn run_frame(&mut self) -> Result<(), EmuError> {
+        while self.frame_cycles < CYCLES_PER_FRAME {
+            let info = self.step()?;
+            self.frame_cycles += info.cycles as u32;
+        }
+        self.frame_cycles -= CYCLES_PER_FRAME;
+        Ok(())
+    }
+
+    /// Snapshot of everything the emulated machine can observe. Serial sinks,
+    /// audio output and button state stay with the host.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        let registers = &self.registers;
+        for register in [
+            registers.a,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            u8::from(registers.f),
+            registers.h,
+            registers.l,
+        ] {
+            writer.write_u8(register);
+        }
+        writer.write_u16(self.pc);
+        writer.write_u16(self.sp);
+        writer.write_bool(self.is_halted);
+        writer.write_bool(self.is_locked);
+        writer.write_u32(self.frame_cycles);
+        writer.write_bytes(&self.bus.memory);
+        self.bus.joypad.save_state(&mut writer);
+        self.bus.gpu.save_state(&mut writer);
+        self.bus.apu.save_state(&mut writer);
+        self.bus.serial.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), StateError> {
+        let reader = StateReader::new(bytes)?;
+        // Every section has a fixed size, so a state of the wrong length is
+        // caught before anything is overwritten.
+        if bytes.len() < self.state_size {
+            return Err(StateError::Truncated);
+        }
+        if bytes.len() > self.state_size {
+            return Err(StateError::Invalid("length"));
+        }
+        // Components check their fields as they read them. Reading into a
+        // scratch machine first means a state that fails a check leaves this
+        // one as it was.
+        Cpu::new(None, Vec::new()).read_state(reader.clone())?;
+        self.read_state(reader)
+    }
+
+    fn read_state(&mut self, mut reader: StateReader) -> Result<(), StateError> {
+        let registers = &mut self.registers;
+        registers.a = reader.read_u8()?;
+        registers.b = reader.read_u8()?;
+        registers.c = reader.read_u8()?;
+        registers.d = reader.read_u8()?;
+        registers.e = reader.read_u8()?;
+        registers.f = registers::FlagsRegister::from(reader.read_u8()?);
+        registers.h = reader.read_u8()?;
+        registers.l = reader.read_u8()?;
+        self.pc = reader.read_u16()?;
+        self.sp = reader.read_u16()?;
+        self.is_halted = reader.read_bool()?;
+        self.is_locked = reader.read_bool()?;
+        self.frame_cycles = reader.read_u32()?.min(CYCLES_PER_FRAME);
+        reader.read_bytes(&mut self.bus.memory)?;
+        self.bus.joypad.load_state(&mut reader)?;
+        self.bus.gpu.load_state(&mut reader)?;
+        self.bus.apu.load_state(&mut reader)?;
+        self.bus.serial.load_state(&mut reader)
+    }
+
+    pub fn step(&mut self) -> Result<StepInfo, EmuError> {
+        let pc = self.pc;
+        let (opcode, prefixed) = self.current_opcode();
+
+        // A locked CPU never fetches again, but the rest of the machine runs on.
+        if self.is_locked {
+            self.bus.tick(4);
+            return Ok(StepInfo {
+                pc,
+                opcode,
+                prefixed,
+                cycles: 4,
+            });
+        }
+
+        self.write_trace_line();
+        let Some(instruction) = Instruction::from_byte(opcode, prefixed) else {
+            if !prefixed && ILLEGAL_OPCODES.contains(&opcode) {
+                self.is_locked = true;
+                return Err(EmuError::IllegalOpcode { pc, opcode });
+            }
+            return Err(self.unimplemented("instruction"));
         };
+        self.pc = self.execute(instruction)?;
 
-        self.pc = next_pc;
+        let cycles = Instruction::cycles(opcode, prefixed);
+        self.bus.tick(cycles);
+        if let Some(address) = self.bus.fault.take() {
+            return Err(EmuError::BusFault {
+                pc,
+                opcode,
+                address,
+            });
+        }
+        Ok(StepInfo {
+            pc,
+            opcode,
+            prefixed,
+            cycles,
+        })
     }
 
     fn call(&mut self, should_jump: bool) -> u16 {
@@ -481,24 +1082,28 @@ impl Cpu {
 
     fn return_(&mut self, should_jump: bool) -> u16 {
         if should_jump {
-            self.pop()
+            let address = self.pop();
+            self.bus.idle();
+            address
         } else {
             self.pc.wrapping_add(1)
         }
     }
 
     fn read_next_byte(&self) -> u8 {
-        self.bus.read_byte(self.pc + 1)
+        self.bus.read_byte(self.pc.wrapping_add(1))
     }
 
     fn read_next_word(&self) -> u16 {
-        ((self.bus.read_byte(self.pc + 2) as u16) << 8) | (self.bus.read_byte(self.pc + 1) as u16)
+        ((self.bus.read_byte(self.pc.wrapping_add(2)) as u16) << 8)
+            | (self.bus.read_byte(self.pc.wrapping_add(1)) as u16)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::sync::{Arc, Mutex};
 
     macro_rules! test_instruction {
         ( $instruction:expr, $( $($register:ident).* => $value:expr ),* ) => {
@@ -507,7 +1112,7 @@ mod tests {
                 $(
                     cpu.registers$(.$register)* = $value;
                 )*
-                cpu.execute($instruction);
+                cpu.execute($instruction).unwrap();
                 cpu
             }
         };
@@ -609,4 +1214,287 @@ mod tests {
         assert_eq!(cpu.registers.a, 0x0F);
         check_flags!(cpu, zero => false, subtract => true, half_carry => true, carry => false);
     }
+
+    #[test]
+    fn test_serial_transfer_raises_interrupt() {
+        let capture = SerialCapture::new();
+        let mut cpu = Cpu::new(None, vec![0; 0xFFFF]);
+        cpu.set_serial_sink(Box::new(capture.clone()));
+        cpu.bus.write_byte(0xFF01, b'!');
+        cpu.bus.write_byte(0xFF02, 0x81);
+
+        for _ in 0..1024 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(capture.text(), "!");
+        assert_eq!(
+            cpu.bus.read_byte(0xFF0F) & SERIAL_INTERRUPT,
+            SERIAL_INTERRUPT
+        );
+    }
+
+    #[test]
+    fn test_illegal_opcode_locks_cpu() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100] = 0xD3;
+        let mut cpu = Cpu::new(None, rom);
+
+        assert_eq!(
+            cpu.step(),
+            Err(EmuError::IllegalOpcode {
+                pc: 0x0100,
+                opcode: 0xD3
+            })
+        );
+        assert!(cpu.is_locked());
+        let info = cpu.step().unwrap();
+        assert_eq!(info.pc, 0x0100);
+        assert_eq!(cpu.pc, 0x0100);
+    }
+
+    #[test]
+    fn test_unimplemented_opcode_reports_context() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100] = 0x00;
+        rom[0x0101] = 0xCB;
+        rom[0x0102] = 0x37;
+        let mut cpu = Cpu::new(None, rom);
+
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.step(),
+            Err(EmuError::Unimplemented {
+                pc: 0x0101,
+                opcode: 0x37,
+                prefixed: true,
+                feature: "instruction"
+            })
+        );
+        assert!(!cpu.is_locked());
+        assert_eq!(cpu.pc, 0x0101);
+        assert_eq!(
+            cpu.step().unwrap_err().to_string(),
+            "unimplemented instruction for opcode 0xCB37 (SWAP A) at 0x0101"
+        );
+        assert_eq!(cpu.disassemble(0x0101).text, "SWAP A");
+    }
+
+    #[test]
+    fn test_bank_switch_is_a_bus_fault() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x01; // MBC1
+                            // LD A,1; LD (HL),A; LD A,2; LD (HL),A
+        rom[0x0100..0x0106].copy_from_slice(&[0x3E, 0x01, 0x77, 0x3E, 0x02, 0x77]);
+        let mut cpu = Cpu::new(None, rom);
+        cpu.registers.set_hl(0x2000);
+
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(
+            cpu.step(),
+            Err(EmuError::BusFault {
+                pc: 0x0105,
+                opcode: 0x77,
+                address: 0x2000
+            })
+        );
+        assert_eq!(cpu.bus.peek(0x2000), 0x00);
+        assert_eq!(
+            EmuError::BusFault {
+                pc: 0x0105,
+                opcode: 0x77,
+                address: 0x2000
+            }
+            .to_string(),
+            "bus fault accessing 0x2000 from opcode 0x77 at 0x0105"
+        );
+    }
+
+    #[derive(Clone)]
+    struct SharedLog(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedLog {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cpu_is_send() {
+        // Serial sinks and trace logs are Send so a machine can run on its own
+        // thread.
+        fn assert_send<T: Send>() {}
+        assert_send::<Cpu>();
+    }
+
+    #[test]
+    fn test_trace_log_uses_gameboy_doctor_format() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0101..0x0104].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x014D] = 0xE7;
+        let mut cpu = Cpu::new(None, rom);
+        cpu.bus.set_ly_stub(true);
+        let log = SharedLog(Arc::new(Mutex::new(Vec::new())));
+        cpu.set_trace_log(Some(Box::new(log.clone())));
+
+        cpu.step().unwrap();
+        assert_eq!(
+            String::from_utf8(log.0.lock().unwrap().clone()).unwrap(),
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01\n"
+        );
+        assert_eq!(cpu.bus.read_byte(0xFF44), 0x90);
+
+        log.0.lock().unwrap().clear();
+        cpu.set_trace_symbols(Some(Symbols::parse("00:0100 Entry").unwrap()));
+        // JP is not decoded yet, but the line is written before that shows.
+        cpu.step().unwrap_err();
+        assert!(String::from_utf8(log.0.lock().unwrap().clone())
+            .unwrap()
+            .ends_with("PC:0101 PCMEM:C3,50,01,00 ; Entry+$1\n"));
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        cpu.registers.a = 0x12;
+        cpu.registers.f.carry = true;
+        cpu.bus.write_byte(0xC000, 0x34);
+        cpu.bus.write_byte(0xFF40, 0x91);
+        cpu.run_frame().unwrap();
+        let state = cpu.save_state();
+
+        cpu.run_frame().unwrap();
+        cpu.registers.a = 0;
+        cpu.bus.write_byte(0xC000, 0);
+        cpu.load_state(&state).unwrap();
+
+        assert_eq!(cpu.registers.a, 0x12);
+        assert!(cpu.registers.f.carry);
+        assert_eq!(cpu.bus.read_byte(0xC000), 0x34);
+        assert_eq!(cpu.save_state(), state);
+        assert_eq!(
+            cpu.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        );
+        assert_eq!(cpu.load_state(b"nope"), Err(StateError::BadMagic));
+        let mut long = state.clone();
+        long.push(0);
+        assert_eq!(cpu.load_state(&long), Err(StateError::Invalid("length")));
+    }
+
+    #[test]
+    fn test_rejected_state_leaves_machine_alone() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let mut state = cpu.save_state();
+        let mut writer = StateWriter::new();
+        cpu.bus.joypad.save_state(&mut writer);
+        let joypad_size = writer.into_bytes().len() - 5;
+        // Magic and version, registers, pc, sp, halt and lock flags, frame
+        // cycles, memory and the joypad, then the PPU's VRAM, OAM, LCDC, STAT,
+        // SCY and SCX before LY.
+        let ly = 5 + 8 + 4 + 2 + 4 + 0x10000 + joypad_size + gpu::VRAM_SIZE + gpu::OAM_SIZE + 4;
+        state[ly] = 200;
+        state[5] = 0x77;
+
+        assert_eq!(cpu.load_state(&state), Err(StateError::Invalid("LY")));
+        assert_eq!(cpu.registers.a, 0x01);
+    }
+
+    #[test]
+    fn test_trace_records_flat_memory_accesses() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0100] = 0x77; // LD (HL),A
+        let mut cpu = Cpu::new(None, rom);
+        cpu.bus.set_flat(true);
+        cpu.registers.a = 0x5A;
+        cpu.registers.set_hl(0xFF40);
+
+        cpu.bus.start_trace();
+        cpu.step().unwrap();
+        let trace = cpu.bus.take_trace();
+        assert_eq!(
+            trace.last(),
+            Some(&Some(BusAccess {
+                address: 0xFF40,
+                value: 0x5A,
+                kind: AccessKind::Write
+            }))
+        );
+
+        cpu.set_pc(0x0200);
+        cpu.bus.write_byte(0x0200, 0x03); // INC BC
+        cpu.bus.start_trace();
+        cpu.step().unwrap();
+        let kinds: Vec<_> = cpu
+            .bus
+            .take_trace()
+            .iter()
+            .map(|cycle| cycle.map(|access| access.kind))
+            .collect();
+        assert_eq!(kinds, [Some(AccessKind::Read), None]);
+        assert_eq!(cpu.bus.memory[0xFF40], 0x5A);
+        assert_eq!(cpu.bus.gpu.read_register(0xFF40), 0x91);
+    }
+
+    #[test]
+    fn test_post_boot_state_depends_on_model() {
+        let cpu = Cpu::new(None, vec![0; 0x8000]);
+        assert_eq!((cpu.pc, cpu.sp), (0x0100, 0xFFFE));
+        assert_eq!(cpu.registers.get_af(), 0x0180);
+        assert_eq!(cpu.bus.read_byte(0xFF40), 0x91);
+        assert_eq!(cpu.bus.read_byte(0xFF47), 0xFC);
+
+        let cpu = Cpu::with_model(Model::Cgb, None, vec![0; 0x8000]);
+        assert_eq!(cpu.registers.get_af(), 0x1180);
+        assert_eq!(cpu.registers.get_hl(), 0x000D);
+
+        let cpu = Cpu::new(Some(vec![0; 0x100]), vec![0; 0x8000]);
+        assert_eq!((cpu.pc, cpu.registers.get_af()), (0x0000, 0x0000));
+    }
+
+    #[test]
+    fn test_post_boot_io_depends_on_model() {
+        let io = |model: Model, address: u16| {
+            Cpu::with_model(model, None, vec![0; 0x8000])
+                .bus
+                .read_byte(address)
+        };
+        let stat = |model: Model| {
+            model
+                .post_boot_io()
+                .iter()
+                .find(|&&(address, _)| address == 0xFF41)
+                .map(|&(_, value)| value)
+        };
+        assert_eq!(stat(Model::Dmg0), Some(0x81));
+        assert_eq!(stat(Model::Dmg), Some(0x85));
+
+        assert_eq!(io(Model::Dmg, 0xFF26), 0xF1);
+        assert_eq!(io(Model::Sgb, 0xFF26), 0xF0);
+        assert_eq!(io(Model::Dmg, 0xFF02), 0x7E);
+        assert_eq!(io(Model::Cgb, 0xFF02), 0x7F);
+        assert_eq!(io(Model::Dmg, 0xFF4D), 0xFF);
+        assert_eq!(io(Model::Cgb, 0xFF4D), 0x7E);
+        assert_eq!(io(Model::Dmg, 0xFF70), 0xFF);
+        assert_eq!(io(Model::Cgb, 0xFF70), 0xF8);
+        assert_eq!(io(Model::Mgb, 0xFF48), 0xFF);
+        assert_eq!(io(Model::Dmg, 0xFF46), 0xFF);
+        assert_eq!(io(Model::Cgb, 0xFF46), 0x00);
+        // Setting DMA must not have started a transfer into OAM.
+        assert_eq!(io(Model::Dmg, 0xFE00), 0x00);
+    }
+
+    #[test]
+    fn test_oam_dma_copies_page() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        cpu.bus.write_byte(0xC09F, 0xAB);
+        cpu.bus.write_byte(0xFF46, 0xC0);
+        assert_eq!(cpu.bus.read_byte(0xFE9F), 0xAB);
+    }
 }
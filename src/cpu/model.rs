@@ -0,0 +1,213 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The hardware revision being emulated. Without a boot ROM this decides the
+/// register and I/O values a cartridge starts with, which some games use to
+/// detect the console they run on.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Model {
+    Dmg0,
+    #[default]
+    Dmg,
+    Mgb,
+    Sgb,
+    Cgb,
+}
+
+/// CPU registers as the boot ROM leaves them.
+pub struct PostBootRegisters {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+}
+
+// Expands to a model's post-boot I/O table: NR52 first, so the APU is powered
+// before its other registers are written, then the registers every model
+// leaves with the same value, then the given ones. The APU registers are
+// written with the trigger bits clear, so no channel starts playing.
+macro_rules! post_boot_io {
+    (nr52: $nr52:expr, $($address:expr => $value:expr,)*) => {
+        &[
+            (0xFF26, $nr52),
+            (0xFF00, 0xCF),
+            (0xFF05, 0x00),
+            (0xFF06, 0x00),
+            (0xFF07, 0xF8),
+            (0xFF0F, 0xE1),
+            (0xFF10, 0x80),
+            (0xFF11, 0xBF),
+            (0xFF12, 0xF3),
+            (0xFF13, 0xFF),
+            (0xFF14, 0x3F),
+            (0xFF16, 0x3F),
+            (0xFF18, 0xFF),
+            (0xFF19, 0x3F),
+            (0xFF1A, 0x7F),
+            (0xFF1B, 0xFF),
+            (0xFF1C, 0x9F),
+            (0xFF1D, 0xFF),
+            (0xFF1E, 0x3F),
+            (0xFF20, 0xFF),
+            (0xFF23, 0x3F),
+            (0xFF24, 0x77),
+            (0xFF25, 0xF3),
+            (0xFF40, 0x91),
+            (0xFF47, 0xFC),
+            (0xFF48, 0xFF),
+            (0xFF49, 0xFF),
+            (0xFF50, 0x01),
+            (0xFFFF, 0x00),
+            $(($address, $value),)*
+        ]
+    };
+}
+
+pub const DIV_ADDRESS: u16 = 0xFF04;
+
+impl Model {
+    /// `header_checksum` is the byte at 0x014D. The DMG and MGB boot ROMs
+    /// leave H and C set unless it is zero.
+    pub fn post_boot_registers(self, header_checksum: u8) -> PostBootRegisters {
+        let half_carry_carry = if header_checksum == 0 { 0x00 } else { 0x30 };
+        match self {
+            Model::Dmg0 => PostBootRegisters {
+                af: 0x0100,
+                bc: 0xFF13,
+                de: 0x00C1,
+                hl: 0x8403,
+            },
+            Model::Dmg => PostBootRegisters {
+                af: 0x0180 | half_carry_carry,
+                bc: 0x0013,
+                de: 0x00D8,
+                hl: 0x014D,
+            },
+            Model::Mgb => PostBootRegisters {
+                af: 0xFF80 | half_carry_carry,
+                bc: 0x0013,
+                de: 0x00D8,
+                hl: 0x014D,
+            },
+            Model::Sgb => PostBootRegisters {
+                af: 0x0100,
+                bc: 0x0014,
+                de: 0x0000,
+                hl: 0xC060,
+            },
+            Model::Cgb => PostBootRegisters {
+                af: 0x1180,
+                bc: 0x0000,
+                de: 0xFF56,
+                hl: 0x000D,
+            },
+        }
+    }
+
+    /// I/O registers as the boot ROM leaves them, in the order to write them.
+    /// NR52's channel bits and STAT's mode and coincidence bits are listed as
+    /// read back, though the APU and PPU only take the writable bits.
+    pub fn post_boot_io(self) -> &'static [(u16, u8)] {
+        match self {
+            Model::Dmg0 => post_boot_io!(nr52: 0xF1,
+                0xFF02 => 0x7E,
+                0xFF41 => 0x81,
+                0xFF46 => 0xFF,
+                0xFF4D => 0xFF,
+                0xFF4F => 0xFF,
+                0xFF51 => 0xFF,
+                0xFF52 => 0xFF,
+                0xFF53 => 0xFF,
+                0xFF54 => 0xFF,
+                0xFF55 => 0xFF,
+                0xFF68 => 0xFF,
+                0xFF6A => 0xFF,
+                0xFF70 => 0xFF,
+            ),
+            Model::Dmg | Model::Mgb => post_boot_io!(nr52: 0xF1,
+                0xFF02 => 0x7E,
+                0xFF41 => 0x85,
+                0xFF46 => 0xFF,
+                0xFF4D => 0xFF,
+                0xFF4F => 0xFF,
+                0xFF51 => 0xFF,
+                0xFF52 => 0xFF,
+                0xFF53 => 0xFF,
+                0xFF54 => 0xFF,
+                0xFF55 => 0xFF,
+                0xFF68 => 0xFF,
+                0xFF6A => 0xFF,
+                0xFF70 => 0xFF,
+            ),
+            // The SGB boot ROM hands the sound off to the SNES and leaves
+            // channel 1 off.
+            Model::Sgb => post_boot_io!(nr52: 0xF0,
+                0xFF02 => 0x7E,
+                0xFF41 => 0x85,
+                0xFF46 => 0xFF,
+                0xFF4D => 0xFF,
+                0xFF4F => 0xFF,
+                0xFF51 => 0xFF,
+                0xFF52 => 0xFF,
+                0xFF53 => 0xFF,
+                0xFF54 => 0xFF,
+                0xFF55 => 0xFF,
+                0xFF68 => 0xFF,
+                0xFF6A => 0xFF,
+                0xFF70 => 0xFF,
+            ),
+            Model::Cgb => post_boot_io!(nr52: 0xF1,
+                0xFF02 => 0x7F,
+                0xFF41 => 0x85,
+                0xFF46 => 0x00,
+                0xFF4D => 0x7E,
+                0xFF4F => 0xFE,
+                0xFF51 => 0xFF,
+                0xFF52 => 0xFF,
+                0xFF53 => 0xFF,
+                0xFF54 => 0xFF,
+                0xFF55 => 0xFF,
+                0xFF68 => 0xC0,
+                0xFF6A => 0xC0,
+                0xFF70 => 0xF8,
+            ),
+        }
+    }
+
+    // The divider has been counting since power on; how far depends on how
+    // long the boot ROM took.
+    pub fn post_boot_div(self) -> u8 {
+        match self {
+            Model::Dmg0 => 0x18,
+            Model::Dmg | Model::Mgb => 0xAB,
+            Model::Sgb | Model::Cgb => 0x00,
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Model, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg0" => Ok(Model::Dmg0),
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "cgb" => Ok(Model::Cgb),
+            _ => Err(format!("unknown model: {}", name)),
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Model::Dmg0 => "dmg0",
+            Model::Dmg => "dmg",
+            Model::Mgb => "mgb",
+            Model::Sgb => "sgb",
+            Model::Cgb => "cgb",
+        })
+    }
+}
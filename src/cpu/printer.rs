@@ -0,0 +1,393 @@
+use std::path::{Path, PathBuf};
+
+use super::image::{save_grayscale_png, SHADES};
+use super::serial::SerialSink;
+
+pub const PRINTER_WIDTH: usize = 160;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+const STATUS_PRINTING: u8 = 0x02;
+const STATUS_IMAGE_FULL: u8 = 0x04;
+const STATUS_UNPROCESSED: u8 = 0x08;
+const STATUS_PACKET_ERROR: u8 = 0x10;
+
+const ALIVE: u8 = 0x81;
+const TILES_PER_ROW: usize = PRINTER_WIDTH / 8;
+const BYTES_PER_TILE_ROW: usize = TILES_PER_ROW * 16;
+// Nine data packets fill the printer's buffer, enough for a whole screen.
+const IMAGE_FULL_SIZE: usize = 9 * 2 * BYTES_PER_TILE_ROW;
+// Each unit of the print command's margins feeds this many blank lines.
+const MARGIN_LINES: usize = 8;
+// Status requests that keep reporting the printing bit after a print.
+const PRINTING_POLLS: u8 = 3;
+
+#[derive(Copy, Clone, PartialEq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// A printed strip: 160 pixels wide, one grayscale byte per pixel.
+pub struct PrintedImage {
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    /// Where the strip was saved, if it was.
+    pub path: Option<PathBuf>,
+}
+
+/// Game Boy Printer on the other end of the serial link. Every completed print
+/// command is rendered with its palette and margins and saved as a PNG in the
+/// output directory, when one is set.
+pub struct Printer {
+    output_dir: Option<PathBuf>,
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    packet: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    printing_polls: u8,
+    image: Vec<u8>,
+    printed: Vec<PrintedImage>,
+    error: Option<String>,
+}
+
+impl Printer {
+    pub fn new(output_dir: Option<&Path>) -> Printer {
+        Printer {
+            output_dir: output_dir.map(Path::to_path_buf),
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            printing_polls: 0,
+            image: Vec::new(),
+            printed: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn printed(&self) -> &[PrintedImage] {
+        &self.printed
+    }
+
+    /// Why the last print could not be saved, until it is taken.
+    pub fn last_error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    fn receive_byte(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.state = match self.state {
+            PacketState::Magic1 if byte == 0x88 => PacketState::Magic2,
+            PacketState::Magic1 => PacketState::Magic1,
+            PacketState::Magic2 if byte == 0x33 => PacketState::Command,
+            PacketState::Magic2 => PacketState::Magic1,
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                PacketState::Compression
+            }
+            PacketState::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthLow
+            }
+            PacketState::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthHigh
+            }
+            PacketState::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.clear();
+                if self.length == 0 {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.length as usize {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                PacketState::ChecksumHigh
+            }
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                PacketState::Alive
+            }
+            PacketState::Alive => {
+                reply = ALIVE;
+                self.process_packet();
+                PacketState::Status
+            }
+            PacketState::Status => {
+                reply = self.status;
+                if self.printing_polls > 0 {
+                    self.printing_polls -= 1;
+                    if self.printing_polls == 0 {
+                        self.status &= !STATUS_PRINTING;
+                    }
+                }
+                PacketState::Magic1
+            }
+        };
+        reply
+    }
+
+    fn process_packet(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.printing_polls = 0;
+            }
+            COMMAND_DATA => {
+                let data = if self.compressed {
+                    decompress(&self.packet)
+                } else {
+                    std::mem::take(&mut self.packet)
+                };
+                // Data past what the buffer holds is dropped, as the real
+                // printer's RAM ends there.
+                let room = IMAGE_FULL_SIZE - self.image.len();
+                if data.len() > room {
+                    self.status |= STATUS_PACKET_ERROR;
+                }
+                self.image.extend_from_slice(&data[..data.len().min(room)]);
+                self.status |= STATUS_UNPROCESSED;
+                if self.image.len() >= IMAGE_FULL_SIZE {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            COMMAND_PRINT if self.packet.len() >= 4 => {
+                let margins = self.packet[1];
+                let palette = self.packet[2];
+                self.print(margins, palette);
+            }
+            COMMAND_STATUS => {}
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, margins: u8, palette: u8) {
+        let (height, pixels) = render(&self.image, margins, palette);
+        self.image.clear();
+        self.status &= !(STATUS_UNPROCESSED | STATUS_IMAGE_FULL);
+        self.status |= STATUS_PRINTING;
+        self.printing_polls = PRINTING_POLLS;
+
+        let mut path = self
+            .output_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("print-{:04}.png", self.printed.len() + 1)));
+        if let Some(file) = &path {
+            if let Err(error) =
+                save_grayscale_png(file, PRINTER_WIDTH as u32, height as u32, &pixels)
+            {
+                self.error = Some(format!(
+                    "could not save print to {}: {}",
+                    file.display(),
+                    error
+                ));
+                path = None;
+            }
+        }
+        self.printed.push(PrintedImage {
+            height,
+            pixels,
+            path,
+        });
+    }
+}
+
+impl SerialSink for Printer {
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        Some(self.receive_byte(byte))
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}
+
+// Run-length decoding used by compressed data packets: a control byte with the
+// high bit set repeats the next byte (n & 0x7F) + 2 times, otherwise the next
+// n + 1 bytes are copied as-is.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut index = 0;
+    while index < data.len() {
+        let control = data[index];
+        index += 1;
+        if control & 0x80 != 0 {
+            let Some(&byte) = data.get(index) else {
+                break;
+            };
+            let count = (control & 0x7F) as usize + 2;
+            output.extend(std::iter::repeat_n(byte, count));
+            index += 1;
+        } else {
+            let count = control as usize + 1;
+            let end = (index + count).min(data.len());
+            output.extend_from_slice(&data[index..end]);
+            index = end;
+        }
+    }
+    output
+}
+
+fn render(image: &[u8], margins: u8, palette: u8) -> (usize, Vec<u8>) {
+    let palette = if palette == 0 { 0xE4 } else { palette };
+    let top = (margins >> 4) as usize * MARGIN_LINES;
+    let bottom = (margins & 0x0F) as usize * MARGIN_LINES;
+    let image_lines = image.len() / BYTES_PER_TILE_ROW * 8;
+    let height = top + image_lines + bottom;
+
+    let mut pixels = vec![SHADES[0]; PRINTER_WIDTH * height];
+    for y in 0..image_lines {
+        for x in 0..PRINTER_WIDTH {
+            let tile = (y / 8) * TILES_PER_ROW + x / 8;
+            let offset = tile * 16 + (y % 8) * 2;
+            let mask = 0x80 >> (x % 8);
+            let low = (image[offset] & mask != 0) as u8;
+            let high = (image[offset + 1] & mask != 0) as u8;
+            let shade = (palette >> ((high << 1 | low) * 2)) & 0x03;
+            pixels[(top + y) * PRINTER_WIDTH + x] = SHADES[shade as usize];
+        }
+    }
+    (height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> u8 {
+        let header = [
+            command,
+            compressed as u8,
+            data.len() as u8,
+            (data.len() >> 8) as u8,
+        ];
+        let checksum = header
+            .iter()
+            .chain(data)
+            .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+
+        for byte in [0x88, 0x33].iter().chain(&header).chain(data) {
+            assert_eq!(printer.receive_byte(*byte), 0x00);
+        }
+        printer.receive_byte(checksum as u8);
+        printer.receive_byte((checksum >> 8) as u8);
+        assert_eq!(printer.receive_byte(0x00), ALIVE);
+        printer.receive_byte(0x00)
+    }
+
+    #[test]
+    fn test_decompress_runs_and_literals() {
+        let data = [0x81, 0xAA, 0x01, 0x01, 0x02];
+        assert_eq!(decompress(&data), vec![0xAA, 0xAA, 0xAA, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn test_print_renders_strip_with_palette_and_margins() {
+        let dir = std::env::temp_dir().join("rustyboy_printer_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut printer = Printer::new(Some(&dir));
+        assert_eq!(send_packet(&mut printer, COMMAND_INIT, false, &[]), 0x00);
+
+        // Two tile rows where every pixel uses color 3, compressed as five
+        // runs of 128 bytes.
+        let run = [0xFE, 0xFF];
+        let data: Vec<u8> = run.iter().cycle().take(5 * 2).copied().collect();
+        let status = send_packet(&mut printer, COMMAND_DATA, true, &data);
+        assert_eq!(status & STATUS_UNPROCESSED, STATUS_UNPROCESSED);
+
+        let status = send_packet(&mut printer, COMMAND_PRINT, false, &[1, 0x11, 0xE4, 0x40]);
+        assert_eq!(status & STATUS_PRINTING, STATUS_PRINTING);
+
+        let printed = &printer.printed()[0];
+        assert_eq!(printed.height, 8 + 16 + 8);
+        assert_eq!(printed.pixels[0], SHADES[0]);
+        assert_eq!(printed.pixels[8 * PRINTER_WIDTH], SHADES[3]);
+        assert_eq!(printed.pixels[(8 + 16) * PRINTER_WIDTH], SHADES[0]);
+
+        let path = printed.path.as_ref().unwrap();
+        let decoder = png::Decoder::new(std::fs::File::open(path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().width, PRINTER_WIDTH as u32);
+        assert_eq!(reader.info().height, printed.height as u32);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(printer.last_error(), None);
+
+        // The directory is gone, so the next print cannot be saved.
+        send_packet(&mut printer, COMMAND_DATA, true, &data);
+        send_packet(&mut printer, COMMAND_PRINT, false, &[1, 0x11, 0xE4, 0x40]);
+        assert!(printer.printed()[1].path.is_none());
+        assert!(printer
+            .take_error()
+            .unwrap()
+            .starts_with("could not save print to"));
+        assert_eq!(printer.last_error(), None);
+    }
+
+    #[test]
+    fn test_data_past_buffer_is_dropped() {
+        let mut printer = Printer::new(None);
+        let data = vec![0xFF; 2 * BYTES_PER_TILE_ROW];
+        for _ in 0..9 {
+            let status = send_packet(&mut printer, COMMAND_DATA, false, &data);
+            assert_eq!(status & STATUS_PACKET_ERROR, 0);
+        }
+        let status = send_packet(&mut printer, COMMAND_DATA, false, &data);
+        assert_eq!(status & STATUS_PACKET_ERROR, STATUS_PACKET_ERROR);
+        assert_eq!(status & STATUS_IMAGE_FULL, STATUS_IMAGE_FULL);
+        assert_eq!(printer.image.len(), IMAGE_FULL_SIZE);
+    }
+
+    #[test]
+    fn test_bad_checksum_sets_error() {
+        let mut printer = Printer::new(None);
+        for byte in [0x88, 0x33, COMMAND_STATUS, 0x00, 0x00, 0x00, 0x00, 0x00] {
+            printer.receive_byte(byte);
+        }
+        assert_eq!(printer.receive_byte(0x00), ALIVE);
+        assert_eq!(printer.receive_byte(0x00), STATUS_CHECKSUM_ERROR);
+    }
+}
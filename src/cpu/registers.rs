@@ -9,6 +9,12 @@ pub struct Registers {
     pub l: u8,
 }
 
+impl Default for Registers {
+    fn default() -> Registers {
+        Registers::new()
+    }
+}
+
 impl Registers {
     pub fn new() -> Registers {
         Registers {
@@ -67,6 +73,12 @@ pub struct FlagsRegister {
     pub carry: bool,
 }
 
+impl Default for FlagsRegister {
+    fn default() -> FlagsRegister {
+        FlagsRegister::new()
+    }
+}
+
 impl FlagsRegister {
     pub fn new() -> Self {
         FlagsRegister {
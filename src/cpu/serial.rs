@@ -0,0 +1,247 @@
+use std::sync::{Arc, Mutex};
+
+use super::state::{StateError, StateReader, StateWriter};
+
+pub const SB_ADDRESS: usize = 0xFF01;
+pub const SC_ADDRESS: usize = 0xFF02;
+
+// The internal clock runs at 8192Hz, one bit every 512 T-cycles.
+const CYCLES_PER_BIT: u32 = 512;
+
+const SC_TRANSFER_START: u8 = 0x80;
+const SC_INTERNAL_CLOCK: u8 = 0x01;
+
+/// The other end of the serial port. Each byte shifted out is handed over once
+/// the transfer completes, and the returned byte is what was shifted in.
+pub trait SerialSink: Send {
+    /// Called when a transfer on the internal clock begins.
+    fn start(&mut self, _byte: u8) {}
+
+    /// Called once the eight bit-times are up, and again on later ticks while
+    /// it returns `None` because the partner's byte has not arrived yet.
+    fn transfer(&mut self, byte: u8) -> Option<u8>;
+
+    /// Polled once per bit-time while the port is not driving the clock.
+    /// `outgoing` holds SB when a transfer is armed; returns the byte the
+    /// partner clocked in, if any.
+    fn receive(&mut self, _outgoing: Option<u8>) -> Option<u8> {
+        None
+    }
+
+    /// A problem the sink ran into since it was last asked, such as a lost
+    /// connection.
+    fn take_error(&mut self) -> Option<String> {
+        None
+    }
+}
+
+/// Collects every transmitted byte. Clones share the same buffer, so a test can
+/// keep one handle while the emulator owns another.
+#[derive(Clone, Default)]
+pub struct SerialCapture {
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SerialCapture {
+    pub fn new() -> SerialCapture {
+        SerialCapture::default()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.bytes.lock().unwrap().clone()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.bytes.lock().unwrap()).into_owned()
+    }
+
+    pub fn clear(&self) {
+        self.bytes.lock().unwrap().clear();
+    }
+}
+
+impl SerialSink for SerialCapture {
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        self.bytes.lock().unwrap().push(byte);
+        // Nothing is connected, so the line floats high.
+        Some(0xFF)
+    }
+}
+
+pub struct Serial {
+    data: u8,
+    control: u8,
+    outgoing: u8,
+    bits_remaining: u8,
+    timer: u32,
+    sink: Box<dyn SerialSink>,
+}
+
+impl Default for Serial {
+    fn default() -> Serial {
+        Serial::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            data: 0,
+            control: 0,
+            outgoing: 0,
+            bits_remaining: 0,
+            timer: 0,
+            sink: Box::new(SerialCapture::new()),
+        }
+    }
+
+    pub fn set_sink(&mut self, sink: Box<dyn SerialSink>) {
+        self.sink = sink;
+    }
+
+    pub fn take_sink_error(&mut self) -> Option<String> {
+        self.sink.take_error()
+    }
+
+    pub fn read_register(&self, address: usize) -> u8 {
+        match address {
+            SB_ADDRESS => self.data,
+            _ => self.control | 0x7E,
+        }
+    }
+
+    pub fn write_register(&mut self, address: usize, value: u8) {
+        match address {
+            SB_ADDRESS => self.data = value,
+            _ => {
+                self.control = value & (SC_TRANSFER_START | SC_INTERNAL_CLOCK);
+                if self.control == SC_TRANSFER_START | SC_INTERNAL_CLOCK {
+                    self.outgoing = self.data;
+                    self.bits_remaining = 8;
+                    self.timer = CYCLES_PER_BIT;
+                    self.sink.start(self.outgoing);
+                } else {
+                    self.timer = 0;
+                }
+            }
+        }
+    }
+
+    // The sink is host-side and is not part of the saved state.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.data);
+        writer.write_u8(self.control);
+        writer.write_u8(self.outgoing);
+        writer.write_u8(self.bits_remaining);
+        writer.write_u32(self.timer);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.data = reader.read_u8()?;
+        self.control = reader.read_u8()?;
+        self.outgoing = reader.read_u8()?;
+        self.bits_remaining = reader.read_u8()?;
+        self.timer = reader.read_u32()?;
+        Ok(())
+    }
+
+    // Returns true when a transfer completed and the serial interrupt should fire.
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        if self.control == SC_TRANSFER_START | SC_INTERNAL_CLOCK {
+            self.tick_internal(cycles)
+        } else {
+            self.tick_external(cycles)
+        }
+    }
+
+    fn tick_internal(&mut self, cycles: u32) -> bool {
+        let mut remaining = cycles;
+        while remaining > 0 && self.bits_remaining > 0 {
+            let elapsed = remaining.min(self.timer);
+            self.timer -= elapsed;
+            remaining -= elapsed;
+            if self.timer == 0 {
+                self.data = (self.data << 1) | 0x01;
+                self.bits_remaining -= 1;
+                self.timer = CYCLES_PER_BIT;
+            }
+        }
+
+        if self.bits_remaining > 0 {
+            return false;
+        }
+        let Some(data) = self.sink.transfer(self.outgoing) else {
+            return false;
+        };
+        self.data = data;
+        self.control &= !SC_TRANSFER_START;
+        true
+    }
+
+    fn tick_external(&mut self, cycles: u32) -> bool {
+        self.timer += cycles;
+        if self.timer < CYCLES_PER_BIT {
+            return false;
+        }
+        self.timer %= CYCLES_PER_BIT;
+
+        let armed = self.control == SC_TRANSFER_START;
+        match self.sink.receive(armed.then_some(self.data)) {
+            Some(incoming) => {
+                self.data = incoming;
+                self.control &= !SC_TRANSFER_START;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(serial: &mut Serial, byte: u8) -> bool {
+        serial.write_register(SB_ADDRESS, byte);
+        serial.write_register(SC_ADDRESS, 0x81);
+        serial.tick(8 * CYCLES_PER_BIT)
+    }
+
+    #[test]
+    fn test_transfer_takes_eight_bit_times() {
+        let mut serial = Serial::new();
+        serial.write_register(SB_ADDRESS, b'P');
+        serial.write_register(SC_ADDRESS, 0x81);
+
+        assert!(!serial.tick(8 * CYCLES_PER_BIT - 1));
+        assert_eq!(serial.read_register(SC_ADDRESS), 0xFF);
+        assert!(serial.tick(1));
+        assert_eq!(serial.read_register(SC_ADDRESS), 0x7F);
+        assert_eq!(serial.read_register(SB_ADDRESS), 0xFF);
+    }
+
+    #[test]
+    fn test_capture_collects_text() {
+        let capture = SerialCapture::new();
+        let mut serial = Serial::new();
+        serial.set_sink(Box::new(capture.clone()));
+
+        for byte in b"Passed" {
+            assert!(send(&mut serial, *byte));
+        }
+        assert_eq!(capture.text(), "Passed");
+    }
+
+    #[test]
+    fn test_external_clock_waits_for_partner() {
+        let capture = SerialCapture::new();
+        let mut serial = Serial::new();
+        serial.set_sink(Box::new(capture.clone()));
+        serial.write_register(SB_ADDRESS, 0x42);
+        serial.write_register(SC_ADDRESS, 0x80);
+
+        assert!(!serial.tick(16 * CYCLES_PER_BIT));
+        assert_eq!(serial.read_register(SC_ADDRESS), 0xFE);
+        assert!(capture.bytes().is_empty());
+    }
+}
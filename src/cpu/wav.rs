@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_SIZE: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// Writes interleaved 16-bit PCM samples to a RIFF/WAVE file. The size fields in
+/// the header are patched in by `finish`, which also runs on drop.
+pub struct WavWriter {
+    file: Option<BufWriter<File>>,
+    data_size: u32,
+}
+
+impl WavWriter {
+    pub fn create(
+        path: impl AsRef<Path>,
+        sample_rate: u32,
+        channels: u16,
+    ) -> io::Result<WavWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let block_align = channels * BITS_PER_SAMPLE / 8;
+
+        file.write_all(b"RIFF")?;
+        file.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        file.write_all(b"WAVE")?;
+        file.write_all(b"fmt ")?;
+        file.write_all(&16u32.to_le_bytes())?;
+        file.write_all(&1u16.to_le_bytes())?;
+        file.write_all(&channels.to_le_bytes())?;
+        file.write_all(&sample_rate.to_le_bytes())?;
+        file.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        file.write_all(&block_align.to_le_bytes())?;
+        file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        file.write_all(b"data")?;
+        file.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            file: Some(file),
+            data_size: 0,
+        })
+    }
+
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(&value.to_le_bytes())?;
+            self.data_size += 2;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        let Some(mut file) = self.file.take() else {
+            return Ok(());
+        };
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        file.seek(SeekFrom::Start(40))?;
+        file.write_all(&self.data_size.to_le_bytes())?;
+        file.flush()
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_sizes_are_patched_on_finish() {
+        let path = std::env::temp_dir().join("rustyboy_wav_header_test.wav");
+        let mut writer = WavWriter::create(&path, 48_000, 2).unwrap();
+        writer.write_sample(1.0).unwrap();
+        writer.write_sample(-1.0).unwrap();
+        writer.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(bytes.len(), 48);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 40);
+        assert_eq!(u16::from_le_bytes(bytes[22..24].try_into().unwrap()), 2);
+        assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 4);
+        assert_eq!(
+            i16::from_le_bytes(bytes[44..46].try_into().unwrap()),
+            i16::MAX
+        );
+        assert_eq!(
+            i16::from_le_bytes(bytes[46..48].try_into().unwrap()),
+            -i16::MAX
+        );
+    }
+}
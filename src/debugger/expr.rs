@@ -0,0 +1,409 @@
+//! Expressions for breakpoint conditions and tracepoint messages, e.g.
+//! `A == $3C && [HL] > 10 && LY == 144`.
+//!
+//! Numbers are decimal unless prefixed with `$`, `0x` or `%` (binary), as in
+//! RGBDS. Names are CPU registers and flags as `debugger::register` takes them,
+//! I/O registers such as `LY` or symbols, which stand for their address, and
+//! `[address]` reads a byte of memory.
+//! Comparisons and logic operators give 1 or 0.
+
+use std::fmt;
+
+use crate::{Cpu, Symbols};
+
+/// I/O registers that expressions can name.
+pub const IO_REGISTERS: [(&str, u16); 44] = [
+    ("P1", 0xFF00),
+    ("JOYP", 0xFF00),
+    ("SB", 0xFF01),
+    ("SC", 0xFF02),
+    ("DIV", 0xFF04),
+    ("TIMA", 0xFF05),
+    ("TMA", 0xFF06),
+    ("TAC", 0xFF07),
+    ("IF", 0xFF0F),
+    ("NR10", 0xFF10),
+    ("NR11", 0xFF11),
+    ("NR12", 0xFF12),
+    ("NR13", 0xFF13),
+    ("NR14", 0xFF14),
+    ("NR21", 0xFF16),
+    ("NR22", 0xFF17),
+    ("NR23", 0xFF18),
+    ("NR24", 0xFF19),
+    ("NR30", 0xFF1A),
+    ("NR31", 0xFF1B),
+    ("NR32", 0xFF1C),
+    ("NR33", 0xFF1D),
+    ("NR34", 0xFF1E),
+    ("NR41", 0xFF20),
+    ("NR42", 0xFF21),
+    ("NR43", 0xFF22),
+    ("NR44", 0xFF23),
+    ("NR50", 0xFF24),
+    ("NR51", 0xFF25),
+    ("NR52", 0xFF26),
+    ("LCDC", 0xFF40),
+    ("STAT", 0xFF41),
+    ("SCY", 0xFF42),
+    ("SCX", 0xFF43),
+    ("LY", 0xFF44),
+    ("LYC", 0xFF45),
+    ("DMA", 0xFF46),
+    ("BGP", 0xFF47),
+    ("OBP0", 0xFF48),
+    ("OBP1", 0xFF49),
+    ("WY", 0xFF4A),
+    ("WX", 0xFF4B),
+    ("BANK", 0xFF50),
+    ("IE", 0xFFFF),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(i64),
+    Register(String),
+    Memory(Box<Node>),
+    Unary(char, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+}
+
+// Binary operators from loosest to tightest binding.
+const PRECEDENCE: [&[&str]; 10] = [
+    &["||"],
+    &["&&"],
+    &["==", "!="],
+    &["<=", ">=", "<", ">"],
+    &["|"],
+    &["^"],
+    &["&"],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    source: String,
+    node: Node,
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        Expr::parse_with_symbols(source, &Symbols::new())
+    }
+
+    pub fn parse_with_symbols(source: &str, symbols: &Symbols) -> Result<Expr, String> {
+        let mut parser = Parser {
+            text: source,
+            position: 0,
+            symbols,
+        };
+        let node = parser.expression(0)?;
+        parser.skip_space();
+        if parser.position < source.len() {
+            return Err(format!(
+                "unexpected `{}` in expression",
+                &source[parser.position..]
+            ));
+        }
+        Ok(Expr {
+            source: source.trim().to_string(),
+            node,
+        })
+    }
+
+    pub fn evaluate(&self, cpu: &Cpu) -> i64 {
+        evaluate(&self.node, cpu)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn evaluate(node: &Node, cpu: &Cpu) -> i64 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Register(name) => match super::register(cpu, name) {
+            Some(value) => value as i64,
+            None => {
+                let address = IO_REGISTERS
+                    .iter()
+                    .find(|(io, _)| io.eq_ignore_ascii_case(name))
+                    .map_or(0, |(_, address)| *address);
+                cpu.bus().peek(address) as i64
+            }
+        },
+        Node::Memory(address) => cpu.bus().peek(evaluate(address, cpu) as u16) as i64,
+        Node::Unary(operator, operand) => {
+            let value = evaluate(operand, cpu);
+            match operator {
+                '-' => value.wrapping_neg(),
+                '~' => !value,
+                _ => (value == 0) as i64,
+            }
+        }
+        Node::Binary(operator, left, right) => {
+            let left = evaluate(left, cpu);
+            // Short-circuit so `[HL] ...` behind a failed test is not read.
+            match *operator {
+                "&&" => return (left != 0 && evaluate(right, cpu) != 0) as i64,
+                "||" => return (left != 0 || evaluate(right, cpu) != 0) as i64,
+                _ => {}
+            }
+            let right = evaluate(right, cpu);
+            match *operator {
+                "==" => (left == right) as i64,
+                "!=" => (left != right) as i64,
+                "<" => (left < right) as i64,
+                "<=" => (left <= right) as i64,
+                ">" => (left > right) as i64,
+                ">=" => (left >= right) as i64,
+                "|" => left | right,
+                "^" => left ^ right,
+                "&" => left & right,
+                "<<" => left.wrapping_shl(right as u32),
+                ">>" => left.wrapping_shr(right as u32),
+                "+" => left.wrapping_add(right),
+                "-" => left.wrapping_sub(right),
+                "*" => left.wrapping_mul(right),
+                "/" => left.checked_div(right).unwrap_or(0),
+                _ => left.checked_rem(right).unwrap_or(0),
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+    symbols: &'a Symbols,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_space(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expression(&mut self, level: usize) -> Result<Node, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut left = self.expression(level + 1)?;
+        'operators: loop {
+            for operator in PRECEDENCE[level] {
+                // One-character operators must not eat the first half of
+                // `||`, `&&`, `<<` or `>>`.
+                let doubled = operator.len() == 1
+                    && self.rest().trim_start().get(..2) == Some(operator.repeat(2).as_str());
+                if !doubled && self.eat(operator) {
+                    let right = self.expression(level + 1)?;
+                    left = Node::Binary(operator, Box::new(left), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        for operator in ['-', '~', '!'] {
+            if self.eat(&operator.to_string()) {
+                return Ok(Node::Unary(operator, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        if self.eat("(") {
+            let node = self.expression(0)?;
+            if !self.eat(")") {
+                return Err("missing `)` in expression".to_string());
+            }
+            return Ok(node);
+        }
+        if self.eat("[") {
+            let node = self.expression(0)?;
+            if !self.eat("]") {
+                return Err("missing `]` in expression".to_string());
+            }
+            return Ok(Node::Memory(Box::new(node)));
+        }
+
+        self.skip_space();
+        let rest = self.rest();
+        let prefix = if rest.starts_with(['$', '%']) { 1 } else { 0 };
+        let length = rest[prefix..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "_.#@".contains(c)))
+            .map_or(rest.len(), |length| prefix + length);
+        let word = &rest[..length];
+        if word.is_empty() {
+            return Err(match rest.chars().next() {
+                Some(c) => format!("unexpected `{}` in expression", c),
+                None => "expression ends early".to_string(),
+            });
+        }
+        self.position += length;
+
+        let number = if let Some(digits) = word.strip_prefix('$') {
+            i64::from_str_radix(digits, 16)
+        } else if let Some(digits) = word.strip_prefix("0x") {
+            i64::from_str_radix(digits, 16)
+        } else if let Some(digits) = word.strip_prefix('%') {
+            i64::from_str_radix(digits, 2)
+        } else if word.starts_with(|c: char| c.is_ascii_digit()) {
+            word.parse()
+        } else {
+            let known = super::REGISTER_NAMES.contains(&word.to_ascii_lowercase().as_str())
+                || IO_REGISTERS
+                    .iter()
+                    .any(|(io, _)| io.eq_ignore_ascii_case(word));
+            if known {
+                return Ok(Node::Register(word.to_string()));
+            }
+            return match self.symbols.lookup(word) {
+                Some((_, address)) => Ok(Node::Number(address as i64)),
+                None => Err(format!("unknown register or symbol: {}", word)),
+            };
+        };
+        number
+            .map(Node::Number)
+            .map_err(|_| format!("invalid number: {}", word))
+    }
+}
+
+enum Segment {
+    Text(String),
+    Value(Expr, bool),
+}
+
+/// A tracepoint message: text with `{expr}` replaced by the value in hex, or
+/// `{expr:d}` in decimal. `{{` and `}}` are literal braces.
+pub struct Message {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Message {
+    pub fn parse(source: &str) -> Result<Message, String> {
+        Message::parse_with_symbols(source, &Symbols::new())
+    }
+
+    pub fn parse_with_symbols(source: &str, symbols: &Symbols) -> Result<Message, String> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut rest = source;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                text.push(c);
+                rest = &rest[2..];
+            } else if c == '{' {
+                let end = rest.find('}').ok_or("missing `}` in message")?;
+                let inner = &rest[1..end];
+                let (expr, decimal) = match inner.strip_suffix(":d") {
+                    Some(expr) => (expr, true),
+                    None => (inner, false),
+                };
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+                segments.push(Segment::Value(
+                    Expr::parse_with_symbols(expr, symbols)?,
+                    decimal,
+                ));
+                rest = &rest[end + 1..];
+            } else {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        segments.push(Segment::Text(text));
+        Ok(Message {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    pub fn format(&self, cpu: &Cpu) -> String {
+        let mut message = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => message.push_str(text),
+                Segment::Value(expr, true) => message.push_str(&expr.evaluate(cpu).to_string()),
+                Segment::Value(expr, false) => {
+                    let value = expr.evaluate(cpu);
+                    let width = if (0..=0xFF).contains(&value) { 2 } else { 4 };
+                    message.push_str(&format!("${:0width$X}", value, width = width));
+                }
+            }
+        }
+        message
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluates_registers_memory_and_precedence() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        cpu.registers_mut().a = 0x3C;
+        cpu.registers_mut().set_hl(0xC000);
+        cpu.bus_mut().write_byte(0xC000, 11);
+
+        let eval = |source: &str| Expr::parse(source).unwrap().evaluate(&cpu);
+        assert_eq!(eval("A == $3C && [HL] > 10 && LY == 0"), 1);
+        assert_eq!(eval("a == $3c && [hl] > 11"), 0);
+        assert_eq!(eval("1 + 2 * 3 == 7 || 0"), 1);
+        assert_eq!(eval("(hl + 1) & $FF | %1000"), 9);
+        assert_eq!(eval("1 | 2 & 4"), 1);
+        assert_eq!(eval("3 ^ 1 | 4"), 6);
+        assert_eq!(eval("-(0 - $7FFFFFFFFFFFFFFF - 1) < 0"), 1);
+        assert_eq!(eval("!zf != 1"), 1);
+        assert_eq!(eval("-1 / 0"), 0);
+        assert_eq!(eval("1 << 4 > a%7"), 1);
+        assert!(Expr::parse("A == ").is_err());
+        assert!(Expr::parse("Q == 1").is_err());
+        assert!(Expr::parse("[HL").is_err());
+
+        let symbols = Symbols::parse("00:c000 wLives\n00:c001 wLives.max").unwrap();
+        let eval = |source: &str| {
+            Expr::parse_with_symbols(source, &symbols)
+                .unwrap()
+                .evaluate(&cpu)
+        };
+        assert_eq!(eval("[wLives] == 11 && wLives.max == $C001"), 1);
+    }
+
+    #[test]
+    fn test_message_interpolation() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        cpu.registers_mut().a = 0x3C;
+        let message = Message::parse("A={a} PC={pc} A*2={a*2:d} {{x}}").unwrap();
+        assert_eq!(message.format(&cpu), "A=$3C PC=$0100 A*2=120 {x}");
+    }
+}
@@ -0,0 +1,454 @@
+//! A GDB remote serial protocol stub, so GDB and front ends built on it can
+//! debug a ROM over TCP.
+//!
+//! GDB has no SM83 target; registers are sent in the order of the Z80 target's
+//! first six, AF, BC, DE, HL, SP and PC, as 16-bit little-endian values.
+//!
+//! GDB knows nothing of the ROM's symbols either, so `monitor` commands give
+//! access to them: `monitor where` names the current address, `monitor break
+//! NAME` and `monitor delete NAME` set and remove breakpoints by symbol.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use super::{Debugger, Stop};
+use crate::cpu::{AccessKind, WatchKind, Watchpoint, CYCLES_PER_FRAME};
+use crate::{Cpu, Symbols};
+
+const REGISTERS: [&str; 6] = ["af", "bc", "de", "hl", "sp", "pc"];
+
+const INTERRUPT: u8 = 0x03;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+enum Received {
+    Packet(String),
+    /// A packet whose checksum did not match its body.
+    Corrupt,
+    Interrupt,
+}
+
+enum Action {
+    Reply(String),
+    Continue,
+    Close(Option<String>),
+}
+
+/// Waits for one GDB connection on `address` and serves it until GDB detaches
+/// or kills the session.
+pub fn serve(cpu: &mut Cpu, address: &str, symbols: Symbols) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    let mut stub = GdbStub::new();
+    stub.set_symbols(symbols);
+    stub.run(cpu, stream)
+}
+
+pub struct GdbStub {
+    debugger: Debugger<'static>,
+    no_ack: bool,
+}
+
+impl Default for GdbStub {
+    fn default() -> GdbStub {
+        GdbStub::new()
+    }
+}
+
+impl GdbStub {
+    pub fn new() -> GdbStub {
+        GdbStub {
+            debugger: Debugger::new(),
+            no_ack: false,
+        }
+    }
+
+    /// Symbols for `monitor` commands to name addresses with.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.debugger.set_symbols(symbols);
+    }
+
+    pub fn run(&mut self, cpu: &mut Cpu, stream: TcpStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        loop {
+            let Some(received) = read_packet(&mut reader)? else {
+                return Ok(());
+            };
+            let packet = match received {
+                Received::Packet(packet) => packet,
+                // GDB sends the packet again when it gets `-` back; without
+                // acknowledgements there is nothing to do but drop it.
+                Received::Corrupt => {
+                    if !self.no_ack {
+                        writer.write_all(b"-")?;
+                        writer.flush()?;
+                    }
+                    continue;
+                }
+                // The target is already stopped; `resume` watches for
+                // interrupts while it runs.
+                Received::Interrupt => continue,
+            };
+            if !self.no_ack {
+                writer.write_all(b"+")?;
+            }
+            match self.handle(cpu, &packet) {
+                Action::Reply(reply) => send(&mut writer, &reply)?,
+                Action::Continue => {
+                    let reply = self.resume(cpu, &mut reader)?;
+                    send(&mut writer, &reply)?;
+                }
+                Action::Close(reply) => {
+                    if let Some(reply) = reply {
+                        send(&mut writer, &reply)?;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // Runs a frame at a time, checking between frames whether GDB sent an
+    // interrupt.
+    fn resume(&mut self, cpu: &mut Cpu, reader: &mut BufReader<TcpStream>) -> io::Result<String> {
+        loop {
+            let stop = self.debugger.continue_(cpu, CYCLES_PER_FRAME as u64);
+            if stop != Stop::Limit {
+                return Ok(stop_reply(&stop));
+            }
+            if interrupted(reader)? {
+                return Ok(format!("S{:02x}", SIGINT));
+            }
+        }
+    }
+
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> Action {
+        let command = packet.get(..1).unwrap_or_default();
+        let arguments = packet.get(1..).unwrap_or_default();
+        let reply = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => REGISTERS
+                .iter()
+                .map(|name| hex_u16(super::register(cpu, name).unwrap_or_default()))
+                .collect(),
+            "G" => match write_registers(cpu, arguments) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "p" => match usize::from_str_radix(arguments, 16)
+                .ok()
+                .and_then(|index| REGISTERS.get(index))
+            {
+                Some(name) => hex_u16(super::register(cpu, name).unwrap_or_default()),
+                None => "E01".to_string(),
+            },
+            "P" => match write_register(cpu, arguments) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "m" => match read_memory(cpu, arguments) {
+                Some(bytes) => bytes,
+                None => "E01".to_string(),
+            },
+            "M" => match write_memory(cpu, arguments) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "c" => {
+                if let Ok(address) = u16::from_str_radix(arguments, 16) {
+                    cpu.set_pc(address);
+                }
+                return Action::Continue;
+            }
+            "s" => {
+                if let Ok(address) = u16::from_str_radix(arguments, 16) {
+                    cpu.set_pc(address);
+                }
+                stop_reply(&self.debugger.step_into(cpu))
+            }
+            "Z" | "z" => match self.set_breakpoint(cpu, arguments, command == "Z") {
+                Some(()) => "OK".to_string(),
+                None => String::new(),
+            },
+            "H" => "OK".to_string(),
+            "D" => return Action::Close(Some("OK".to_string())),
+            "k" => return Action::Close(None),
+            _ if packet.starts_with("qSupported") => "PacketSize=4000;QStartNoAckMode+".to_string(),
+            _ if packet == "qAttached" => "1".to_string(),
+            _ if packet.starts_with("qRcmd,") => match decode_hex(&packet[6..]) {
+                Some(command) => encode_hex(&self.monitor(cpu, &command)),
+                None => "E01".to_string(),
+            },
+            _ if packet == "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            // Anything else is unsupported, which GDB expects to be an empty reply.
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    // Runs a `monitor` command and returns what it prints.
+    fn monitor(&mut self, cpu: &Cpu, command: &str) -> String {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let result = match words.as_slice() {
+            ["where"] => Ok(self.debugger.describe(cpu.pc())),
+            ["break", name] => self.debugger.resolve(name).map(|address| {
+                self.debugger.add_breakpoint(address);
+                format!("breakpoint at {}", self.debugger.describe(address))
+            }),
+            ["delete", name] => self.debugger.resolve(name).and_then(|address| {
+                let description = self.debugger.describe(address);
+                if self.debugger.remove_breakpoint(address) {
+                    Ok(format!("deleted {}", description))
+                } else {
+                    Err(format!("no breakpoint at {}", description))
+                }
+            }),
+            _ => Err(format!("unknown monitor command: {}", command)),
+        };
+        match result {
+            Ok(output) | Err(output) => output + "\n",
+        }
+    }
+
+    // `type,address,kind`: 0 and 1 are breakpoints, 2-4 write, read and
+    // access watchpoints.
+    fn set_breakpoint(&mut self, cpu: &mut Cpu, arguments: &str, insert: bool) -> Option<()> {
+        let mut fields = arguments.split(',');
+        let kind = fields.next()?;
+        let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.add_breakpoint(address);
+                } else {
+                    self.debugger.remove_breakpoint(address);
+                }
+                return Some(());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return None,
+        };
+        if insert {
+            let watchpoint = Watchpoint {
+                address,
+                kind: watch,
+            };
+            self.debugger.add_watchpoint(cpu, watchpoint);
+        } else {
+            self.debugger.remove_watchpoints(cpu, address);
+        }
+        Some(())
+    }
+}
+
+fn stop_reply(stop: &Stop) -> String {
+    match stop {
+        Stop::Watchpoint(access) => {
+            let kind = match access.kind {
+                AccessKind::Read => "rwatch",
+                AccessKind::Write => "watch",
+            };
+            format!("T{:02x}{}:{:04x};", SIGTRAP, kind, access.address)
+        }
+        Stop::Error(_) => format!("S{:02x}", SIGILL),
+        Stop::Step | Stop::Breakpoint(_) | Stop::Limit => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn encode_hex(text: &str) -> String {
+    text.bytes().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+fn hex_u16(value: u16) -> String {
+    let [low, high] = value.to_le_bytes();
+    format!("{:02x}{:02x}", low, high)
+}
+
+fn parse_u16_le(hex: &str) -> Option<u16> {
+    let low = u8::from_str_radix(hex.get(0..2)?, 16).ok()?;
+    let high = u8::from_str_radix(hex.get(2..4)?, 16).ok()?;
+    Some(u16::from_le_bytes([low, high]))
+}
+
+fn write_registers(cpu: &mut Cpu, hex: &str) -> Option<()> {
+    for (index, name) in REGISTERS.iter().enumerate() {
+        let value = parse_u16_le(hex.get(index * 4..index * 4 + 4)?)?;
+        super::set_register(cpu, name, value).ok()?;
+    }
+    Some(())
+}
+
+fn write_register(cpu: &mut Cpu, arguments: &str) -> Option<()> {
+    let (index, value) = arguments.split_once('=')?;
+    let name = REGISTERS.get(usize::from_str_radix(index, 16).ok()?)?;
+    super::set_register(cpu, name, parse_u16_le(value)?).ok()
+}
+
+fn memory_range(arguments: &str) -> Option<(u16, u16)> {
+    let (address, length) = arguments.split_once(',')?;
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        u16::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+fn read_memory(cpu: &Cpu, arguments: &str) -> Option<String> {
+    let (address, length) = memory_range(arguments)?;
+    Some(
+        (0..length)
+            .map(|offset| format!("{:02x}", cpu.bus().peek(address.wrapping_add(offset))))
+            .collect(),
+    )
+}
+
+fn write_memory(cpu: &mut Cpu, arguments: &str) -> Option<()> {
+    let (range, data) = arguments.split_once(':')?;
+    let (address, length) = memory_range(range)?;
+    if data.len() != length as usize * 2 {
+        return None;
+    }
+    for offset in 0..length {
+        let index = offset as usize * 2;
+        let value = u8::from_str_radix(data.get(index..index + 2)?, 16).ok()?;
+        cpu.bus_mut().poke(address.wrapping_add(offset), value);
+    }
+    Some(())
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+fn send(writer: &mut impl Write, data: &str) -> io::Result<()> {
+    write!(writer, "${}#{:02x}", data, checksum(data))?;
+    writer.flush()
+}
+
+// Returns `None` at the end of the stream. Acknowledgements are skipped.
+fn read_packet(reader: &mut impl BufRead) -> io::Result<Option<Received>> {
+    let mut byte = [0];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        match byte[0] {
+            b'$' => break,
+            INTERRUPT => return Ok(Some(Received::Interrupt)),
+            _ => {}
+        }
+    }
+    let mut body = Vec::new();
+    reader.read_until(b'#', &mut body)?;
+    body.pop();
+    let mut sum = [0; 2];
+    reader.read_exact(&mut sum)?;
+    let body = String::from_utf8_lossy(&body).into_owned();
+    let sum = std::str::from_utf8(&sum)
+        .ok()
+        .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+    if sum != Some(checksum(&body)) {
+        return Ok(Some(Received::Corrupt));
+    }
+    Ok(Some(Received::Packet(body)))
+}
+
+fn interrupted(reader: &mut BufReader<TcpStream>) -> io::Result<bool> {
+    reader.get_ref().set_nonblocking(true)?;
+    let result = match reader.fill_buf() {
+        Ok(buffer) => Ok(buffer.contains(&INTERRUPT)),
+        Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(error) => Err(error),
+    };
+    reader.get_ref().set_nonblocking(false)?;
+    if result.as_ref().is_ok_and(|interrupted| *interrupted) {
+        let length = reader.buffer().len();
+        reader.consume(length);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(stub: &mut GdbStub, cpu: &mut Cpu, packet: &str) -> String {
+        match stub.handle(cpu, packet) {
+            Action::Reply(reply) => reply,
+            _ => panic!("{} did not reply", packet),
+        }
+    }
+
+    #[test]
+    fn test_registers_memory_and_stepping() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let mut stub = GdbStub::new();
+
+        assert_eq!(reply(&mut stub, &mut cpu, "g"), "80011300d8004d01feff0001");
+        assert_eq!(reply(&mut stub, &mut cpu, "P5=5001"), "OK");
+        assert_eq!(cpu.pc(), 0x0150);
+        assert_eq!(reply(&mut stub, &mut cpu, "Mc000,2:abcd"), "OK");
+        assert_eq!(reply(&mut stub, &mut cpu, "mc000,2"), "abcd");
+        assert_eq!(reply(&mut stub, &mut cpu, "s"), "S05");
+        assert_eq!(cpu.pc(), 0x0151);
+        assert_eq!(reply(&mut stub, &mut cpu, "Z0,160,1"), "OK");
+        assert!(matches!(stub.handle(&mut cpu, "c"), Action::Continue));
+        assert_eq!(
+            stub.debugger.continue_(&mut cpu, 1000),
+            Stop::Breakpoint(0x0160)
+        );
+        assert_eq!(reply(&mut stub, &mut cpu, "vMustReplyEmpty"), "");
+    }
+
+    #[test]
+    fn test_monitor_commands_use_symbols() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let mut stub = GdbStub::new();
+        stub.set_symbols(Symbols::parse("00:0100 Entry\n00:0104 Loop").unwrap());
+        let mut monitor = |command: &str| {
+            let output = reply(
+                &mut stub,
+                &mut cpu,
+                &format!("qRcmd,{}", encode_hex(command)),
+            );
+            decode_hex(&output).unwrap()
+        };
+
+        assert_eq!(monitor("where"), "$0100 <Entry>\n");
+        assert_eq!(monitor("break Loop"), "breakpoint at $0104 <Loop>\n");
+        assert_eq!(monitor("delete 104"), "deleted $0104 <Loop>\n");
+        assert_eq!(monitor("delete Loop"), "no breakpoint at $0104 <Loop>\n");
+        assert_eq!(monitor("jump"), "unknown monitor command: jump\n");
+    }
+
+    #[test]
+    fn test_packets_are_framed_with_checksums() {
+        let mut output = Vec::new();
+        send(&mut output, "OK").unwrap();
+        assert_eq!(output, b"$OK#9a");
+
+        let mut input = "+$m0,1#fa\x03$m0,1#fb$m0,1#zz".as_bytes();
+        let mut read = || read_packet(&mut input).unwrap();
+        assert!(matches!(read(), Some(Received::Packet(body)) if body == "m0,1"));
+        assert!(matches!(read(), Some(Received::Interrupt)));
+        assert!(matches!(read(), Some(Received::Corrupt)));
+        assert!(matches!(read(), Some(Received::Corrupt)));
+        assert!(read().is_none());
+    }
+}
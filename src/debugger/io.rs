@@ -0,0 +1,286 @@
+//! The I/O registers from $FF00 to $FF7F and IE decoded into named fields,
+//! e.g. `FF41 STAT $85 int=none ly=lyc=yes mode=VBlank`.
+//!
+//! Sound registers are shown as last written rather than as read, since most
+//! of their bits read back as 1.
+
+use std::fmt;
+
+use super::expr::IO_REGISTERS;
+use crate::cpu::apu::{APU_BEGIN, APU_END, WAVE_RAM_BEGIN};
+use crate::MemoryBus;
+
+const INTERRUPTS: [&str; 5] = ["VBlank", "STAT", "Timer", "Serial", "Joypad"];
+const TIMER_CLOCKS: [&str; 4] = ["4096Hz", "262144Hz", "65536Hz", "16384Hz"];
+const DUTIES: [&str; 4] = ["12.5%", "25%", "50%", "75%"];
+const WAVE_LEVELS: [&str; 4] = ["mute", "100%", "50%", "25%"];
+const MODES: [&str; 4] = ["HBlank", "VBlank", "OAM", "transfer"];
+
+pub struct Register {
+    pub name: &'static str,
+    pub address: u16,
+    pub value: u8,
+    pub fields: Vec<(&'static str, String)>,
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04X} {:<4} ${:02X}",
+            self.address, self.name, self.value
+        )?;
+        for (name, value) in &self.fields {
+            write!(f, " {}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every named register from $FF00 to $FFFF in address order, then wave RAM
+/// as a whole.
+pub fn registers(bus: &MemoryBus) -> Vec<Register> {
+    let mut registers: Vec<Register> = Vec::new();
+    for (name, address) in IO_REGISTERS {
+        // P1 and JOYP are one register.
+        if registers.last().is_some_and(|last| last.address == address) {
+            continue;
+        }
+        let value = if (APU_BEGIN..=APU_END).contains(&(address as usize)) {
+            bus.apu().written_register(address as usize)
+        } else {
+            bus.peek(address)
+        };
+        registers.push(Register {
+            name,
+            address,
+            value,
+            fields: fields(name, value),
+        });
+    }
+    let wave: String = (WAVE_RAM_BEGIN..=APU_END)
+        .map(|address| format!("{:02X}", bus.apu().written_register(address)))
+        .collect();
+    registers.push(Register {
+        name: "WAVE",
+        address: WAVE_RAM_BEGIN as u16,
+        value: bus.apu().written_register(WAVE_RAM_BEGIN),
+        fields: vec![("samples", wave)],
+    });
+    registers
+}
+
+/// The register with this name, ignoring case.
+pub fn register(bus: &MemoryBus, name: &str) -> Option<Register> {
+    let address = IO_REGISTERS
+        .iter()
+        .find(|(io, _)| io.eq_ignore_ascii_case(name))
+        .map(|(_, address)| *address);
+    registers(bus).into_iter().find(|register| {
+        Some(register.address) == address
+            || register.name == "WAVE" && name.eq_ignore_ascii_case("WAVE")
+    })
+}
+
+fn on(value: u8, bit: u8) -> String {
+    if value & 1 << bit != 0 { "on" } else { "off" }.to_string()
+}
+
+fn bits(value: u8, shift: u8, mask: u8) -> u8 {
+    (value >> shift) & mask
+}
+
+// Names of the set bits, or `none`.
+fn set_bits(value: u8, names: &[&str]) -> String {
+    let set: Vec<&str> = names
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| value & 1 << bit != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    if set.is_empty() {
+        "none".to_string()
+    } else {
+        set.join(",")
+    }
+}
+
+fn map(value: u8, bit: u8) -> String {
+    if value & 1 << bit != 0 {
+        "9C00"
+    } else {
+        "9800"
+    }
+    .to_string()
+}
+
+fn envelope(value: u8) -> Vec<(&'static str, String)> {
+    let direction = if value & 0x08 != 0 { "up" } else { "down" };
+    vec![
+        ("volume", bits(value, 4, 0xF).to_string()),
+        ("env", direction.to_string()),
+        ("pace", bits(value, 0, 0x7).to_string()),
+    ]
+}
+
+fn control(value: u8, period: bool) -> Vec<(&'static str, String)> {
+    let mut fields = vec![("trigger", on(value, 7)), ("length", on(value, 6))];
+    if period {
+        fields.push(("period_hi", bits(value, 0, 0x7).to_string()));
+    }
+    fields
+}
+
+fn palette(value: u8) -> Vec<(&'static str, String)> {
+    let shades: Vec<String> = (0..4)
+        .map(|color| bits(value, color * 2, 0x3).to_string())
+        .collect();
+    vec![("shades", shades.join(""))]
+}
+
+fn fields(name: &str, value: u8) -> Vec<(&'static str, String)> {
+    match name {
+        "P1" => {
+            let select = set_bits(!value >> 4 & 0x3, &["dpad", "buttons"]);
+            let keys = match !value >> 4 & 0x3 {
+                0b01 => ["Right", "Left", "Up", "Down"],
+                0b10 => ["A", "B", "Select", "Start"],
+                _ => ["0", "1", "2", "3"],
+            };
+            vec![
+                ("select", select),
+                ("pressed", set_bits(!value & 0xF, &keys)),
+            ]
+        }
+        "SC" => {
+            let clock = if value & 0x01 != 0 {
+                "internal"
+            } else {
+                "external"
+            };
+            vec![("transfer", on(value, 7)), ("clock", clock.to_string())]
+        }
+        "TAC" => vec![
+            ("enable", on(value, 2)),
+            (
+                "clock",
+                TIMER_CLOCKS[bits(value, 0, 0x3) as usize].to_string(),
+            ),
+        ],
+        "IF" | "IE" => vec![("flags", set_bits(value & 0x1F, &INTERRUPTS))],
+        "NR10" => {
+            let direction = if value & 0x08 != 0 { "down" } else { "up" };
+            vec![
+                ("pace", bits(value, 4, 0x7).to_string()),
+                ("dir", direction.to_string()),
+                ("step", bits(value, 0, 0x7).to_string()),
+            ]
+        }
+        "NR11" | "NR21" => vec![
+            ("duty", DUTIES[bits(value, 6, 0x3) as usize].to_string()),
+            ("length", bits(value, 0, 0x3F).to_string()),
+        ],
+        "NR12" | "NR22" | "NR42" => envelope(value),
+        "NR13" | "NR23" | "NR33" => vec![("period_lo", format!("${:02X}", value))],
+        "NR14" | "NR24" | "NR34" => control(value, true),
+        "NR44" => control(value, false),
+        "NR30" => vec![("dac", on(value, 7))],
+        "NR31" => vec![("length", value.to_string())],
+        "NR32" => vec![(
+            "level",
+            WAVE_LEVELS[bits(value, 5, 0x3) as usize].to_string(),
+        )],
+        "NR41" => vec![("length", bits(value, 0, 0x3F).to_string())],
+        "NR43" => {
+            let width = if value & 0x08 != 0 { "7" } else { "15" };
+            vec![
+                ("shift", bits(value, 4, 0xF).to_string()),
+                ("width", width.to_string()),
+                ("divider", bits(value, 0, 0x7).to_string()),
+            ]
+        }
+        "NR50" => vec![
+            ("vin_l", on(value, 7)),
+            ("left", bits(value, 4, 0x7).to_string()),
+            ("vin_r", on(value, 3)),
+            ("right", bits(value, 0, 0x7).to_string()),
+        ],
+        "NR51" => {
+            let channels = ["1", "2", "3", "4"];
+            vec![
+                ("left", set_bits(value >> 4, &channels)),
+                ("right", set_bits(value & 0xF, &channels)),
+            ]
+        }
+        "NR52" => vec![
+            ("power", on(value, 7)),
+            ("active", set_bits(value & 0xF, &["1", "2", "3", "4"])),
+        ],
+        "LCDC" => {
+            let tiles = if value & 0x10 != 0 { "8000" } else { "8800" };
+            let size = if value & 0x04 != 0 { "8x16" } else { "8x8" };
+            vec![
+                ("lcd", on(value, 7)),
+                ("win_map", map(value, 6)),
+                ("win", on(value, 5)),
+                ("tiles", tiles.to_string()),
+                ("bg_map", map(value, 3)),
+                ("obj_size", size.to_string()),
+                ("obj", on(value, 1)),
+                ("bg", on(value, 0)),
+            ]
+        }
+        "STAT" => {
+            let compare = if value & 0x04 != 0 { "yes" } else { "no" };
+            vec![
+                (
+                    "int",
+                    set_bits(value >> 3 & 0xF, &["HBlank", "VBlank", "OAM", "LYC"]),
+                ),
+                ("ly=lyc", compare.to_string()),
+                ("mode", MODES[bits(value, 0, 0x3) as usize].to_string()),
+            ]
+        }
+        "SCY" | "SCX" | "LY" | "LYC" | "WY" | "WX" | "DIV" | "TIMA" | "TMA" => {
+            vec![("dec", value.to_string())]
+        }
+        "DMA" => vec![("source", format!("${:02X}00", value))],
+        "BGP" | "OBP0" | "OBP1" => palette(value),
+        "BANK" => {
+            let boot = if value != 0 { "unmapped" } else { "mapped" };
+            vec![("boot_rom", boot.to_string())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cpu;
+
+    #[test]
+    fn test_decodes_post_boot_registers() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        cpu.bus_mut().write_byte(0xFF07, 0x05);
+        cpu.bus_mut().write_byte(0xFF13, 0x83);
+        cpu.bus_mut().write_byte(0xFFFF, 0x05);
+        let bus = cpu.bus();
+
+        let text = |name| register(bus, name).unwrap().to_string();
+        assert_eq!(
+            text("lcdc"),
+            "FF40 LCDC $91 lcd=on win_map=9800 win=off tiles=8000 bg_map=9800 obj_size=8x8 \
+             obj=off bg=on"
+        );
+        assert_eq!(text("TAC"), "FF07 TAC  $05 enable=on clock=262144Hz");
+        assert_eq!(text("NR13"), "FF13 NR13 $83 period_lo=$83");
+        assert_eq!(text("IE"), "FFFF IE   $05 flags=VBlank,Timer");
+        assert_eq!(text("BGP"), "FF47 BGP  $FC shades=0333");
+        assert_eq!(
+            text("JOYP"),
+            "FF00 P1   $CF select=dpad,buttons pressed=none"
+        );
+        assert_eq!(registers(bus).len(), IO_REGISTERS.len());
+    }
+}
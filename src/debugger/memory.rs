@@ -0,0 +1,261 @@
+//! The memory viewer's state: which rows are shown, the selected byte, edits
+//! typed into it, and go-to and search prompts. Keys come in as [`Key`] so any
+//! frontend can drive it.
+
+use crate::symbols::mapped_bank;
+use crate::MemoryBus;
+
+pub const BYTES_PER_ROW: u16 = 16;
+
+/// The memory region an address is in, named as in RGBDS section types.
+pub fn region(address: u16) -> String {
+    match address {
+        0x0000..=0x3FFF => "ROM0".to_string(),
+        0x4000..=0x7FFF => format!("ROMX {:02X}", mapped_bank(address)),
+        0x8000..=0x9FFF => "VRAM".to_string(),
+        0xA000..=0xBFFF => "SRAM".to_string(),
+        0xC000..=0xCFFF => "WRAM0".to_string(),
+        0xD000..=0xDFFF => "WRAMX".to_string(),
+        0xE000..=0xFDFF => "ECHO".to_string(),
+        0xFE00..=0xFE9F => "OAM".to_string(),
+        0xFEA0..=0xFEFF => "UNUSED".to_string(),
+        0xFF00..=0xFF7F => "I/O".to_string(),
+        0xFF80..=0xFFFE => "HRAM".to_string(),
+        0xFFFF => "IE".to_string(),
+    }
+}
+
+/// Hex bytes such as `3E 01` or `3e01`.
+pub fn parse_pattern(text: &str) -> Result<Vec<u8>, String> {
+    let digits: String = text.split_whitespace().collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(format!("invalid byte pattern: {}", text));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&digits[index..index + 2], 16)
+                .map_err(|_| format!("invalid byte pattern: {}", text))
+        })
+        .collect()
+}
+
+/// The first address at or after `from` where `pattern` starts, wrapping
+/// around the end of the address space.
+pub fn find(bus: &MemoryBus, pattern: &[u8], from: u16) -> Option<u16> {
+    (0..=0xFFFF)
+        .map(|offset: u32| from.wrapping_add(offset as u16))
+        .find(|start| {
+            pattern
+                .iter()
+                .enumerate()
+                .all(|(index, byte)| bus.peek(start.wrapping_add(index as u16)) == *byte)
+        })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    PageUp,
+    PageDown,
+}
+
+enum Prompt {
+    GoTo,
+    Search,
+}
+
+/// Hex digits overwrite the selected byte a nibble at a time and move on to the
+/// next one. `g` asks for an address to go to, `/` for a byte pattern to search
+/// for and `n` finds the next match. An empty prompt is cancelled by Enter;
+/// a half-typed byte by selecting another.
+pub struct MemoryEditor {
+    top: u16,
+    rows: u16,
+    cursor: u16,
+    nibble: Option<u8>,
+    prompt: Option<(Prompt, String)>,
+    pattern: Vec<u8>,
+    message: String,
+}
+
+impl MemoryEditor {
+    pub fn new(rows: u16) -> MemoryEditor {
+        MemoryEditor {
+            top: 0xC000,
+            rows,
+            cursor: 0xC000,
+            nibble: None,
+            prompt: None,
+            pattern: Vec::new(),
+            message: String::new(),
+        }
+    }
+
+    /// Address of the first byte shown.
+    pub fn top(&self) -> u16 {
+        self.top
+    }
+
+    pub fn cursor(&self) -> u16 {
+        self.cursor
+    }
+
+    /// True while a prompt is being typed into, when keys are not game input.
+    pub fn is_prompting(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    /// The open prompt, the half-typed byte or the result of the last command.
+    pub fn status(&self) -> String {
+        match (&self.prompt, self.nibble) {
+            (Some((Prompt::GoTo, text)), _) => format!("Go to: {}_", text),
+            (Some((Prompt::Search, text)), _) => format!("Search: {}_", text),
+            (None, Some(nibble)) => format!("${:04X} = {:X}_", self.cursor, nibble),
+            (None, None) => self.message.clone(),
+        }
+    }
+
+    pub fn select(&mut self, address: u16) {
+        self.cursor = address;
+        self.nibble = None;
+    }
+
+    pub fn scroll(&mut self, rows: i32) {
+        let offset = rows * BYTES_PER_ROW as i32;
+        let last_top = 0x10000 - (self.rows * BYTES_PER_ROW) as i32;
+        self.top = (self.top as i32 + offset).clamp(0, last_top) as u16;
+    }
+
+    // Scrolls just far enough to show the cursor.
+    fn follow_cursor(&mut self) {
+        let row = |address: u16| address as i32 / BYTES_PER_ROW as i32;
+        let (cursor, top) = (row(self.cursor), row(self.top));
+        if cursor < top {
+            self.scroll(cursor - top);
+        } else if cursor >= top + self.rows as i32 {
+            self.scroll(cursor - top - self.rows as i32 + 1);
+        }
+    }
+
+    pub fn key(&mut self, bus: &mut MemoryBus, key: Key) {
+        if let Some((prompt, mut text)) = self.prompt.take() {
+            match key {
+                Key::Char(c) if c.is_ascii_hexdigit() || c == ' ' => {
+                    text.push(c);
+                    self.prompt = Some((prompt, text));
+                }
+                Key::Backspace => {
+                    text.pop();
+                    self.prompt = Some((prompt, text));
+                }
+                Key::Enter if text.trim().is_empty() => {}
+                Key::Enter => self.message = self.run_prompt(bus, prompt, &text),
+                _ => self.prompt = Some((prompt, text)),
+            }
+            return;
+        }
+
+        match key {
+            Key::Char(c) if c.is_ascii_hexdigit() => {
+                let digit = c.to_digit(16).unwrap_or_default() as u8;
+                match self.nibble.take() {
+                    None => self.nibble = Some(digit),
+                    Some(high) => {
+                        let value = high << 4 | digit;
+                        bus.poke(self.cursor, value);
+                        self.message = format!("${:04X} = ${:02X}", self.cursor, value);
+                        self.cursor = self.cursor.wrapping_add(1);
+                        self.follow_cursor();
+                    }
+                }
+            }
+            Key::Char('g' | 'G') => self.prompt = Some((Prompt::GoTo, String::new())),
+            Key::Char('/') => self.prompt = Some((Prompt::Search, String::new())),
+            Key::Char('n' | 'N') => {
+                self.message = self.find_next(bus, self.cursor.wrapping_add(1));
+            }
+            Key::PageUp => self.scroll(-(self.rows as i32)),
+            Key::PageDown => self.scroll(self.rows as i32),
+            _ => {}
+        }
+    }
+
+    fn run_prompt(&mut self, bus: &MemoryBus, prompt: Prompt, text: &str) -> String {
+        match prompt {
+            Prompt::GoTo => match u16::from_str_radix(text.trim(), 16) {
+                Ok(address) => {
+                    self.select(address);
+                    self.follow_cursor();
+                    String::new()
+                }
+                Err(_) => format!("invalid address: {}", text.trim()),
+            },
+            Prompt::Search => match parse_pattern(text) {
+                Ok(pattern) => {
+                    self.pattern = pattern;
+                    self.find_next(bus, self.cursor)
+                }
+                Err(error) => error,
+            },
+        }
+    }
+
+    fn find_next(&mut self, bus: &MemoryBus, from: u16) -> String {
+        if self.pattern.is_empty() {
+            return "nothing to search for".to_string();
+        }
+        match find(bus, &self.pattern, from) {
+            Some(address) => {
+                self.select(address);
+                self.follow_cursor();
+                format!("found at ${:04X}", address)
+            }
+            None => "not found".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cpu;
+
+    fn type_text(editor: &mut MemoryEditor, bus: &mut MemoryBus, text: &str) {
+        for c in text.chars() {
+            let key = if c == '\n' { Key::Enter } else { Key::Char(c) };
+            editor.key(bus, key);
+        }
+    }
+
+    #[test]
+    fn test_edits_goes_to_and_searches() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let bus = cpu.bus_mut();
+        let mut editor = MemoryEditor::new(16);
+
+        type_text(&mut editor, bus, "gD0FF\n3e01");
+        assert_eq!((bus.peek(0xD0FF), bus.peek(0xD100)), (0x3E, 0x01));
+        assert_eq!(editor.cursor(), 0xD101);
+        assert_eq!(editor.top(), 0xD010);
+
+        type_text(&mut editor, bus, "gC000\n/3E 01\n");
+        assert_eq!(editor.status(), "found at $D0FF");
+        type_text(&mut editor, bus, "n");
+        assert_eq!(editor.status(), "found at $D0FF");
+        type_text(&mut editor, bus, "/3e0\n");
+        assert_eq!(editor.status(), "invalid byte pattern: 3e0");
+        type_text(&mut editor, bus, "g\n4");
+        assert_eq!(editor.status(), "$D0FF = 4_");
+
+        bus.set_write_tracking(true);
+        bus.write_byte(0xC123, 1);
+        assert_eq!(bus.last_write_frame(0xC123), Some(0));
+        assert_eq!(bus.last_write_frame(0xD0FF), None);
+
+        assert_eq!(region(0x4000), "ROMX 01");
+        assert_eq!(region(0xFF80), "HRAM");
+    }
+}
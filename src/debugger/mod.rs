@@ -0,0 +1,497 @@
+//! Breakpoints, watchpoints and stepping on top of [`Cpu`].
+
+pub mod expr;
+pub mod gdb;
+pub mod io;
+pub mod memory;
+pub mod oam;
+pub mod repl;
+pub mod vram;
+
+use std::collections::BTreeMap;
+
+use crate::cpu::disasm::instruction_length;
+use crate::cpu::{BusAccess, Watchpoint};
+use crate::symbols::{self, Symbols};
+use crate::{Cpu, EmuError};
+
+use self::expr::{Expr, Message};
+
+/// Names `register` and `set_register` accept.
+pub const REGISTER_NAMES: [&str; 18] = [
+    "a", "f", "b", "c", "d", "e", "h", "l", "af", "bc", "de", "hl", "sp", "pc", "zf", "nf", "hf",
+    "cf",
+];
+
+/// Why the debugger handed control back.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stop {
+    /// The requested step finished.
+    Step,
+    /// An instruction at a breakpoint is about to execute.
+    Breakpoint(u16),
+    /// The last instruction touched a watched address.
+    Watchpoint(BusAccess),
+    Error(EmuError),
+    /// The cycle budget ran out first.
+    Limit,
+}
+
+/// A CALL or RST that has not returned yet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub call_site: u16,
+    pub target: u16,
+}
+
+impl Frame {
+    pub fn return_address(&self, cpu: &Cpu) -> u16 {
+        let length = instruction_length(cpu.bus().peek(self.call_site));
+        self.call_site.wrapping_add(length as u16)
+    }
+}
+
+/// What happens when execution reaches a breakpoint's address.
+#[derive(Default)]
+pub struct Breakpoint {
+    /// Only counts as a hit when this is non-zero.
+    pub condition: Option<Expr>,
+    /// Hits to let pass before stopping.
+    pub ignore: u32,
+    /// Times the breakpoint was reached with its condition true.
+    pub hits: u32,
+    /// Makes this a tracepoint: hits log the message instead of stopping.
+    pub message: Option<Message>,
+}
+
+// Where tracepoint messages go as they are logged.
+type Log<'a> = Box<dyn FnMut(&str) + 'a>;
+
+pub struct Debugger<'a> {
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    call_stack: Vec<Frame>,
+    messages: Vec<String>,
+    log: Option<Log<'a>>,
+    symbols: Symbols,
+    // The pc whose breakpoint the last run already evaluated before it
+    // stopped, so the next run starts past it instead of hitting it twice.
+    evaluated_pc: Option<u16>,
+}
+
+impl<'a> Default for Debugger<'a> {
+    fn default() -> Debugger<'a> {
+        Debugger::new()
+    }
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new() -> Debugger<'a> {
+        Debugger {
+            breakpoints: BTreeMap::new(),
+            call_stack: Vec::new(),
+            messages: Vec::new(),
+            log: None,
+            symbols: Symbols::new(),
+            evaluated_pc: None,
+        }
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// `$XXXX`, followed by the nearest symbol when there is one.
+    pub fn describe(&self, address: u16) -> String {
+        match self
+            .symbols
+            .describe(symbols::mapped_bank(address), address)
+        {
+            Some(name) => format!("${:04X} <{}>", address, name),
+            None => format!("${:04X}", address),
+        }
+    }
+
+    /// The address of the symbol `name`, or else `name` as a hex number with
+    /// an optional `$` or `0x`. Symbols in a ROM bank other than the mapped
+    /// one are refused, since their address means something else here.
+    pub fn resolve(&self, name: &str) -> Result<u16, String> {
+        match self.symbols.lookup(name) {
+            Some((bank, address))
+                if bank != symbols::mapped_bank(address) && (0x4000..0x8000).contains(&address) =>
+            {
+                Err(format!(
+                    "{} is in ROM bank {}, which is not mapped",
+                    name, bank
+                ))
+            }
+            Some((_, address)) => Ok(address),
+            None => {
+                let digits = name.trim_start_matches("0x").trim_start_matches('$');
+                u16::from_str_radix(digits, 16).map_err(|_| format!("invalid number: {}", name))
+            }
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.entry(address).or_default();
+    }
+
+    /// Adds or replaces the breakpoint at `address`.
+    pub fn set_breakpoint(&mut self, address: u16, breakpoint: Breakpoint) {
+        self.breakpoints.insert(address, breakpoint);
+    }
+
+    pub fn breakpoint_mut(&mut self, address: u16) -> Option<&mut Breakpoint> {
+        self.breakpoints.get_mut(&address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (u16, &Breakpoint)> + '_ {
+        self.breakpoints
+            .iter()
+            .map(|(address, breakpoint)| (*address, breakpoint))
+    }
+
+    /// Hands tracepoint messages to `log` as they are logged, rather than
+    /// keeping them for `take_messages`.
+    pub fn set_log(&mut self, log: impl FnMut(&str) + 'a) {
+        self.log = Some(Box::new(log));
+    }
+
+    /// Tracepoint messages logged since the last call, when there is no log.
+    pub fn take_messages(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.messages)
+    }
+
+    pub fn add_watchpoint(&mut self, cpu: &mut Cpu, watchpoint: Watchpoint) {
+        cpu.bus_mut().add_watchpoint(watchpoint);
+    }
+
+    pub fn remove_watchpoints(&mut self, cpu: &mut Cpu, address: u16) {
+        cpu.bus_mut().remove_watchpoints(address);
+    }
+
+    /// Calls made since the debugger started watching, innermost last.
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    /// Executes one instruction.
+    pub fn step_into(&mut self, cpu: &mut Cpu) -> Stop {
+        self.run(cpu, u64::MAX, |_, _| true)
+    }
+
+    /// Like `step_into`, but runs a CALL or RST until it returns.
+    pub fn step_over(&mut self, cpu: &mut Cpu, max_cycles: u64) -> Stop {
+        let depth = self.call_stack.len();
+        self.run(cpu, max_cycles, move |debugger, _| {
+            debugger.call_stack.len() <= depth
+        })
+    }
+
+    /// Runs until the current function returns to its caller.
+    pub fn step_out(&mut self, cpu: &mut Cpu, max_cycles: u64) -> Stop {
+        let depth = self.call_stack.len();
+        if depth == 0 {
+            return self.continue_(cpu, max_cycles);
+        }
+        self.run(cpu, max_cycles, move |debugger, _| {
+            debugger.call_stack.len() < depth
+        })
+    }
+
+    /// Runs until `address` is about to execute.
+    pub fn run_to(&mut self, cpu: &mut Cpu, address: u16, max_cycles: u64) -> Stop {
+        self.run(cpu, max_cycles, move |_, cpu| cpu.pc() == address)
+    }
+
+    pub fn continue_(&mut self, cpu: &mut Cpu, max_cycles: u64) -> Stop {
+        self.run(cpu, max_cycles, |_, _| false)
+    }
+
+    // Steps until `done` holds after an instruction, a breakpoint or
+    // watchpoint is hit, or `max_cycles` have run. The breakpoint at the
+    // starting pc is evaluated too, unless the last run stopped there after
+    // evaluating it, so that resuming from a breakpoint makes progress.
+    fn run(
+        &mut self,
+        cpu: &mut Cpu,
+        max_cycles: u64,
+        done: impl Fn(&Debugger, &Cpu) -> bool,
+    ) -> Stop {
+        cpu.bus().take_watch_hit();
+        let resuming = self.evaluated_pc.take() == Some(cpu.pc());
+        if !resuming && self.hit_breakpoint(cpu) {
+            return self.stop_at(cpu, Stop::Breakpoint(cpu.pc()));
+        }
+        let mut cycles = 0;
+        loop {
+            match self.step(cpu) {
+                Ok(step_cycles) => cycles += step_cycles as u64,
+                Err(error) => return Stop::Error(error),
+            }
+            if let Some(access) = cpu.bus().take_watch_hit() {
+                return Stop::Watchpoint(access);
+            }
+            if self.hit_breakpoint(cpu) {
+                return self.stop_at(cpu, Stop::Breakpoint(cpu.pc()));
+            }
+            if done(self, cpu) {
+                return self.stop_at(cpu, Stop::Step);
+            }
+            if cycles >= max_cycles {
+                return self.stop_at(cpu, Stop::Limit);
+            }
+        }
+    }
+
+    fn stop_at(&mut self, cpu: &Cpu, stop: Stop) -> Stop {
+        self.evaluated_pc = Some(cpu.pc());
+        stop
+    }
+
+    // Whether the breakpoint at pc, if any, should stop execution. Tracepoints
+    // log and let execution go on.
+    fn hit_breakpoint(&mut self, cpu: &Cpu) -> bool {
+        let Some(breakpoint) = self.breakpoints.get_mut(&cpu.pc()) else {
+            return false;
+        };
+        if let Some(condition) = &breakpoint.condition {
+            if condition.evaluate(cpu) == 0 {
+                return false;
+            }
+        }
+        breakpoint.hits += 1;
+        if breakpoint.hits <= breakpoint.ignore {
+            return false;
+        }
+        match &breakpoint.message {
+            Some(message) => {
+                let message = message.format(cpu);
+                match &mut self.log {
+                    Some(log) => log(&message),
+                    None => self.messages.push(message),
+                }
+                false
+            }
+            None => true,
+        }
+    }
+
+    // Executes one instruction and keeps the call stack in step with CALL,
+    // RST and RET.
+    fn step(&mut self, cpu: &mut Cpu) -> Result<u8, EmuError> {
+        let pc = cpu.pc();
+        let opcode = cpu.bus().peek(pc);
+        let next = pc.wrapping_add(instruction_length(opcode) as u16);
+        let info = cpu.step()?;
+        let taken = cpu.pc() != next;
+        match opcode {
+            0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC if taken => self.call_stack.push(Frame {
+                call_site: pc,
+                target: cpu.pc(),
+            }),
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => self.call_stack.push(Frame {
+                call_site: pc,
+                target: cpu.pc(),
+            }),
+            0xC0 | 0xC8 | 0xC9 | 0xD0 | 0xD8 | 0xD9 if taken => {
+                self.call_stack.pop();
+            }
+            _ => {}
+        }
+        Ok(info.cycles)
+    }
+}
+
+/// Reads a register or flag by name: `a`..`l`, `af`, `bc`, `de`, `hl`, `sp`,
+/// `pc`, or `zf`, `nf`, `hf` and `cf` for the flags.
+pub fn register(cpu: &Cpu, name: &str) -> Option<u16> {
+    let registers = cpu.registers();
+    let flags = registers.f;
+    Some(match name.to_ascii_lowercase().as_str() {
+        "a" => registers.a as u16,
+        "f" => u8::from(flags) as u16,
+        "b" => registers.b as u16,
+        "c" => registers.c as u16,
+        "d" => registers.d as u16,
+        "e" => registers.e as u16,
+        "h" => registers.h as u16,
+        "l" => registers.l as u16,
+        "af" => registers.get_af(),
+        "bc" => registers.get_bc(),
+        "de" => registers.get_de(),
+        "hl" => registers.get_hl(),
+        "sp" => cpu.sp(),
+        "pc" => cpu.pc(),
+        "zf" => flags.zero as u16,
+        "nf" => flags.subtract as u16,
+        "hf" => flags.half_carry as u16,
+        "cf" => flags.carry as u16,
+        _ => return None,
+    })
+}
+
+/// Writes a register or flag by the names `register` accepts. 8-bit registers
+/// take the low byte of `value` and flags are set when it is non-zero.
+pub fn set_register(cpu: &mut Cpu, name: &str, value: u16) -> Result<(), String> {
+    let byte = value as u8;
+    let set = value != 0;
+    match name.to_ascii_lowercase().as_str() {
+        "sp" => cpu.set_sp(value),
+        "pc" => cpu.set_pc(value),
+        name => {
+            let registers = cpu.registers_mut();
+            match name {
+                "a" => registers.a = byte,
+                "f" => registers.f = byte.into(),
+                "b" => registers.b = byte,
+                "c" => registers.c = byte,
+                "d" => registers.d = byte,
+                "e" => registers.e = byte,
+                "h" => registers.h = byte,
+                "l" => registers.l = byte,
+                "af" => registers.set_af(value),
+                "bc" => registers.set_bc(value),
+                "de" => registers.set_de(value),
+                "hl" => registers.set_hl(value),
+                "zf" => registers.f.zero = set,
+                "nf" => registers.f.subtract = set,
+                "hf" => registers.f.half_carry = set,
+                "cf" => registers.f.carry = set,
+                _ => return Err(format!("unknown register: {}", name)),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{AccessKind, WatchKind};
+
+    #[test]
+    fn test_breakpoints_and_run_to() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x0104);
+
+        assert_eq!(debugger.step_into(&mut cpu), Stop::Step);
+        assert_eq!(cpu.pc(), 0x0101);
+        assert_eq!(debugger.continue_(&mut cpu, 1000), Stop::Breakpoint(0x0104));
+        assert_eq!(debugger.run_to(&mut cpu, 0x0108, 1000), Stop::Step);
+        assert_eq!(cpu.pc(), 0x0108);
+        assert_eq!(debugger.continue_(&mut cpu, 8), Stop::Limit);
+    }
+
+    #[test]
+    fn test_breakpoint_at_cycle_limit() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0x0102);
+
+        // The second NOP uses up the budget and lands on the breakpoint.
+        assert_eq!(debugger.continue_(&mut cpu, 8), Stop::Breakpoint(0x0102));
+        assert_eq!(debugger.continue_(&mut cpu, 8), Stop::Limit);
+        assert_eq!(cpu.pc(), 0x0104);
+
+        // A breakpoint at the pc a run starts from stops it straight away.
+        debugger.add_breakpoint(0x0100);
+        cpu.set_pc(0x0100);
+        assert_eq!(debugger.continue_(&mut cpu, 8), Stop::Breakpoint(0x0100));
+        assert_eq!(cpu.pc(), 0x0100);
+        assert_eq!(debugger.continue_(&mut cpu, 8), Stop::Breakpoint(0x0102));
+    }
+
+    #[test]
+    fn test_conditions_ignore_counts_and_tracepoints() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let mut debugger = Debugger::new();
+        debugger.set_breakpoint(
+            0x0102,
+            Breakpoint {
+                message: Some(Message::parse("pc={pc} a={a:d}").unwrap()),
+                ..Breakpoint::default()
+            },
+        );
+        debugger.set_breakpoint(
+            0x0104,
+            Breakpoint {
+                condition: Some(Expr::parse("A == 2").unwrap()),
+                ..Breakpoint::default()
+            },
+        );
+        debugger.set_breakpoint(
+            0x0106,
+            Breakpoint {
+                ignore: 1,
+                ..Breakpoint::default()
+            },
+        );
+
+        assert_eq!(debugger.run_to(&mut cpu, 0x0108, 1000), Stop::Step);
+        assert_eq!(debugger.take_messages(), vec!["pc=$0102 a=1"]);
+        cpu.set_pc(0x0100);
+        cpu.registers_mut().a = 2;
+        assert_eq!(debugger.continue_(&mut cpu, 1000), Stop::Breakpoint(0x0104));
+        assert_eq!(debugger.continue_(&mut cpu, 1000), Stop::Breakpoint(0x0106));
+        let hits: Vec<u32> = debugger.breakpoints().map(|(_, b)| b.hits).collect();
+        assert_eq!(hits, vec![2, 1, 2]);
+    }
+
+    #[test]
+    fn test_tracepoint_at_start_logs_as_it_runs() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let mut logged = Vec::new();
+        let mut debugger = Debugger::new();
+        debugger.set_log(|message| logged.push(message.to_string()));
+        for address in [0x0100, 0x0102] {
+            let breakpoint = Breakpoint {
+                message: Some(Message::parse("at {pc}").unwrap()),
+                ..Breakpoint::default()
+            };
+            debugger.set_breakpoint(address, breakpoint);
+        }
+
+        assert_eq!(debugger.continue_(&mut cpu, 16), Stop::Limit);
+        assert!(debugger.take_messages().is_empty());
+        let hits: Vec<u32> = debugger.breakpoints().map(|(_, b)| b.hits).collect();
+        assert_eq!(hits, vec![1, 1]);
+        drop(debugger);
+        assert_eq!(logged, vec!["at $0100", "at $0102"]);
+    }
+
+    #[test]
+    fn test_write_watchpoint_stops_after_access() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0102] = 0x77; // LD (HL),A
+        let mut cpu = Cpu::new(None, rom);
+        set_register(&mut cpu, "hl", 0xC123).unwrap();
+        set_register(&mut cpu, "cf", 0).unwrap();
+        assert_eq!(register(&cpu, "f"), Some(0x80));
+
+        let mut debugger = Debugger::new();
+        let watchpoint = Watchpoint {
+            address: 0xC123,
+            kind: WatchKind::Write,
+        };
+        debugger.add_watchpoint(&mut cpu, watchpoint);
+        assert_eq!(
+            debugger.continue_(&mut cpu, 1000),
+            Stop::Watchpoint(BusAccess {
+                address: 0xC123,
+                value: 0x01,
+                kind: AccessKind::Write
+            })
+        );
+        assert_eq!(cpu.pc(), 0x0103);
+    }
+}
@@ -0,0 +1,171 @@
+//! What the OAM inspector shows: every sprite entry, the lines it covers and
+//! the lines where the 10-sprites-per-line limit drops it.
+
+use std::ops::RangeInclusive;
+
+use crate::cpu::gpu::{self, Gpu, OBP0, OBP1, SPRITES_PER_LINE};
+use crate::SCREEN_HEIGHT;
+
+pub const SPRITE_COUNT: usize = 40;
+
+const FLAG_BEHIND_BG: u8 = 0x80;
+const FLAG_FLIP_Y: u8 = 0x40;
+const FLAG_FLIP_X: u8 = 0x20;
+const FLAG_PALETTE: u8 = 0x10;
+
+pub struct Sprite {
+    pub index: usize,
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+    /// Visible lines the sprite overlaps, if any.
+    pub lines: Option<RangeInclusive<usize>>,
+    /// Lines it overlaps but is not drawn on because ten earlier entries were.
+    pub dropped: Vec<usize>,
+}
+
+impl Sprite {
+    /// The flags as `B` (behind the background), `Y` and `X` (flips) and the
+    /// OBP palette number, with `-` for clear bits.
+    pub fn flag_text(&self) -> String {
+        let flag = |mask, letter| if self.flags & mask != 0 { letter } else { '-' };
+        let palette = if self.flags & FLAG_PALETTE != 0 {
+            '1'
+        } else {
+            '0'
+        };
+        [
+            flag(FLAG_BEHIND_BG, 'B'),
+            flag(FLAG_FLIP_Y, 'Y'),
+            flag(FLAG_FLIP_X, 'X'),
+            palette,
+        ]
+        .iter()
+        .collect()
+    }
+}
+
+impl std::fmt::Display for Sprite {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02} X:{:3} Y:{:3} T:${:02X} {}",
+            self.index,
+            self.x,
+            self.y,
+            self.tile,
+            self.flag_text()
+        )?;
+        match &self.lines {
+            Some(lines) => write!(f, " lines {}-{}", lines.start(), lines.end())?,
+            None => write!(f, " offscreen")?,
+        }
+        if let (Some(first), Some(last)) = (self.dropped.first(), self.dropped.last()) {
+            let count = self.dropped.len();
+            write!(f, " dropped on {} lines in {}-{}", count, first, last)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn sprites(gpu: &Gpu) -> Vec<Sprite> {
+    let height = gpu.sprite_height();
+    let mut sprites: Vec<Sprite> = (0..SPRITE_COUNT)
+        .map(|index| {
+            let entry: Vec<u8> = (0..4).map(|byte| gpu.read_oam(index * 4 + byte)).collect();
+            let top = entry[0] as usize;
+            // OAM Y is the line plus 16, so sprites can sit partly above the screen.
+            let first = top.saturating_sub(16);
+            let last = (top + height).saturating_sub(17).min(SCREEN_HEIGHT - 1);
+            Sprite {
+                index,
+                y: entry[0],
+                x: entry[1],
+                tile: entry[2],
+                flags: entry[3],
+                lines: (top + height > 16 && first < SCREEN_HEIGHT).then_some(first..=last),
+                dropped: Vec::new(),
+            }
+        })
+        .collect();
+    for line in 0..SCREEN_HEIGHT {
+        for index in gpu.sprites_on_line(line).skip(SPRITES_PER_LINE) {
+            sprites[index].dropped.push(line);
+        }
+    }
+    sprites
+}
+
+/// The sprite's pixels as shades through its palette, flipped as it is drawn,
+/// 8 wide and `Gpu::sprite_height` tall. Transparent pixels are `None`.
+pub fn preview(gpu: &Gpu, sprite: &Sprite) -> Vec<Option<u8>> {
+    let height = gpu.sprite_height();
+    let palette = gpu.read_register(if sprite.flags & FLAG_PALETTE != 0 {
+        OBP1
+    } else {
+        OBP0
+    });
+    let tile = match height {
+        16 => sprite.tile & 0xFE,
+        _ => sprite.tile,
+    } as usize;
+    let mut pixels = Vec::with_capacity(8 * height);
+    for y in 0..height {
+        let row = if sprite.flags & FLAG_FLIP_Y != 0 {
+            height - 1 - y
+        } else {
+            y
+        };
+        for x in 0..8 {
+            let column = if sprite.flags & FLAG_FLIP_X != 0 {
+                7 - x
+            } else {
+                x
+            };
+            let color = gpu.tile_pixel(tile + row / 8, row % 8, column);
+            pixels.push((color != 0).then(|| gpu::shade(palette, color)));
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lists_lines_and_sprites_over_the_limit() {
+        let mut gpu = Gpu::new();
+        // Eleven sprites on lines 0-7, the last partly above the screen.
+        for index in 0..11 {
+            gpu.write_oam(index * 4, 16);
+            gpu.write_oam(index * 4 + 1, 8 * index as u8);
+        }
+        gpu.write_oam(10 * 4, 12);
+        gpu.write_oam(11 * 4, 160);
+        gpu.write_oam(11 * 4 + 3, 0xF0);
+        // Tile 0 has colour 1 in its top-left pixel only.
+        gpu.write_vram(0, 0x80);
+        gpu.write_register(OBP1, 0b1100);
+
+        let sprites = sprites(&gpu);
+        assert_eq!(
+            sprites[0].to_string(),
+            "00 X:  0 Y: 16 T:$00 ---0 lines 0-7"
+        );
+        assert_eq!(
+            sprites[10].to_string(),
+            "10 X: 80 Y: 12 T:$00 ---0 lines 0-3 dropped on 4 lines in 0-3"
+        );
+        assert_eq!(
+            sprites[11].to_string(),
+            "11 X:  0 Y:160 T:$00 BYX1 offscreen"
+        );
+        assert_eq!(sprites[12].lines, None);
+
+        let preview = preview(&gpu, &sprites[11]);
+        assert_eq!(preview[63], Some(3));
+        assert_eq!(preview[0], None);
+    }
+}
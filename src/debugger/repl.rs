@@ -0,0 +1,487 @@
+use std::cell::RefCell;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+
+use super::expr::{Expr, Message};
+use super::{register, set_register, Breakpoint, Debugger, Stop};
+use crate::cpu::{AccessKind, WatchKind, Watchpoint, CYCLES_PER_FRAME};
+use crate::symbols::mapped_bank;
+use crate::{Cpu, Symbols};
+
+// How long `continue` and friends run before giving control back, so a ROM
+// that never hits a breakpoint does not hang the prompt.
+const RUN_LIMIT_FRAMES: u64 = 60 * 60;
+const RUN_LIMIT: u64 = CYCLES_PER_FRAME as u64 * RUN_LIMIT_FRAMES;
+
+const HELP: &str = "\
+Numbers are hex, optionally prefixed with $ or 0x, except in expressions,
+where they are decimal unless prefixed with $, 0x or %. An empty line repeats
+the last command. Wherever an address is expected, a symbol from the ROM's
+.sym file can be given instead, and expressions can use symbols too.
+
+  s, step [N]            execute N instructions (default 1)
+  n, next                step over CALL and RST
+  finish                 run until the current function returns
+  c, continue            run until a breakpoint or watchpoint
+  u, until ADDR          run until ADDR is about to execute
+  b, break ADDR [if EXPR]
+                         set a breakpoint, optionally only when EXPR holds
+  cond ADDR [EXPR]       change or clear a breakpoint's condition
+  ignore ADDR N          let N hits of a breakpoint pass
+  trace ADDR MESSAGE     log MESSAGE instead of stopping at ADDR; {EXPR} in
+                         it is replaced by the value in hex, {EXPR:d} in decimal
+  d, delete ADDR         remove a breakpoint or tracepoint
+  w, watch ADDR [r|w|rw] stop after ADDR is read and/or written (default w)
+  unwatch ADDR           remove the watchpoints on ADDR
+  i, info                list breakpoints and watchpoints
+  r, regs                show registers
+  p, print EXPR          evaluate an expression, e.g. [HL] + LY * 2
+  set REG VALUE          set a register (a..l, af, bc, de, hl, sp, pc) or
+                         flag (zf, nf, hf, cf)
+  x ADDR [N]             dump N bytes of memory (default 64)
+  l, list [ADDR] [N]     disassemble N instructions (default 8 from pc)
+  io [NAME]              decode one I/O register, e.g. STAT, or all of them
+  bt                     show the call stack
+  q, quit                leave the debugger";
+
+/// Reads debugger commands from `input` until it ends or `quit` is entered.
+pub fn run(
+    cpu: &mut Cpu,
+    symbols: Symbols,
+    input: impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let mut output = SharedOutput(Rc::new(RefCell::new(output)));
+    let mut log = output.clone();
+    let output = &mut output;
+    let mut debugger = Debugger::new();
+    debugger.set_symbols(symbols);
+    // Tracepoints print while the run goes on; a failed write shows up again
+    // at the next prompt.
+    debugger.set_log(move |message| {
+        let _ = writeln!(log, "{}", message);
+    });
+    let mut last = String::new();
+    list(&debugger, cpu, cpu.pc(), output)?;
+    write!(output, "> ")?;
+    output.flush()?;
+
+    for line in input.lines() {
+        let line = line?;
+        let line = if line.trim().is_empty() {
+            last.clone()
+        } else {
+            line
+        };
+        match execute(&mut debugger, cpu, &line, output) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(Error::Command(message)) => writeln!(output, "{}", message)?,
+            Err(Error::Io(error)) => return Err(error),
+        }
+        last = line;
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+// The session's output, shared with the debugger's tracepoint log.
+struct SharedOutput<W>(Rc<RefCell<W>>);
+
+impl<W> Clone for SharedOutput<W> {
+    fn clone(&self) -> Self {
+        SharedOutput(Rc::clone(&self.0))
+    }
+}
+
+impl<W: Write> Write for SharedOutput<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+enum Error {
+    Command(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Error {
+        Error::Command(message)
+    }
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid number: {}", text))
+}
+
+fn argument(words: &[&str], index: usize) -> Result<u16, String> {
+    let word = words
+        .get(index)
+        .ok_or_else(|| format!("{} needs an argument", words[0]))?;
+    parse_number(word)
+}
+
+// A symbol or a hex number.
+fn address(debugger: &Debugger, words: &[&str], index: usize) -> Result<u16, String> {
+    let word = words
+        .get(index)
+        .ok_or_else(|| format!("{} needs an address", words[0]))?;
+    debugger.resolve(word)
+}
+
+fn optional_address(
+    debugger: &Debugger,
+    words: &[&str],
+    index: usize,
+    default: u16,
+) -> Result<u16, String> {
+    match words.get(index) {
+        Some(_) => address(debugger, words, index),
+        None => Ok(default),
+    }
+}
+
+// The line after its first `count` words, for arguments that contain spaces.
+fn rest_of_line(line: &str, count: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..count {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+    }
+    rest
+}
+
+fn optional_argument(words: &[&str], index: usize, default: u16) -> Result<u16, String> {
+    words
+        .get(index)
+        .map_or(Ok(default), |word| parse_number(word))
+}
+
+// Returns true when the session should end.
+fn execute(
+    debugger: &mut Debugger,
+    cpu: &mut Cpu,
+    line: &str,
+    output: &mut impl Write,
+) -> Result<bool, Error> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some(command) = words.first() else {
+        return Ok(false);
+    };
+    match *command {
+        "s" | "step" => {
+            let count = optional_argument(&words, 1, 1)?;
+            let mut stop = Stop::Step;
+            for _ in 0..count {
+                stop = debugger.step_into(cpu);
+                if stop != Stop::Step {
+                    break;
+                }
+            }
+            report(debugger, cpu, &stop, output)?;
+        }
+        "n" | "next" => {
+            let stop = debugger.step_over(cpu, RUN_LIMIT);
+            report(debugger, cpu, &stop, output)?;
+        }
+        "finish" => {
+            let stop = debugger.step_out(cpu, RUN_LIMIT);
+            report(debugger, cpu, &stop, output)?;
+        }
+        "c" | "continue" => {
+            let stop = debugger.continue_(cpu, RUN_LIMIT);
+            report(debugger, cpu, &stop, output)?;
+        }
+        "u" | "until" => {
+            let address = address(debugger, &words, 1)?;
+            let stop = debugger.run_to(cpu, address, RUN_LIMIT);
+            report(debugger, cpu, &stop, output)?;
+        }
+        "b" | "break" => {
+            let address = address(debugger, &words, 1)?;
+            let condition = match words.get(2) {
+                Some(&"if") => Some(Expr::parse_with_symbols(
+                    rest_of_line(line, 3),
+                    debugger.symbols(),
+                )?),
+                Some(word) => return Err(format!("expected `if`, found {}", word).into()),
+                None => None,
+            };
+            let breakpoint = Breakpoint {
+                condition,
+                ..Breakpoint::default()
+            };
+            debugger.set_breakpoint(address, breakpoint);
+            writeln!(output, "breakpoint at {}", debugger.describe(address))?;
+        }
+        "cond" => {
+            let address = address(debugger, &words, 1)?;
+            let condition = match words.len() {
+                2 => None,
+                _ => Some(Expr::parse_with_symbols(
+                    rest_of_line(line, 2),
+                    debugger.symbols(),
+                )?),
+            };
+            breakpoint_at(debugger, address)?.condition = condition;
+        }
+        "ignore" => {
+            let address = address(debugger, &words, 1)?;
+            let count = argument(&words, 2)?;
+            breakpoint_at(debugger, address)?.ignore = count as u32;
+        }
+        "trace" => {
+            let address = address(debugger, &words, 1)?;
+            let message = Message::parse_with_symbols(rest_of_line(line, 2), debugger.symbols())?;
+            let breakpoint = Breakpoint {
+                message: Some(message),
+                ..Breakpoint::default()
+            };
+            debugger.set_breakpoint(address, breakpoint);
+            writeln!(output, "tracepoint at {}", debugger.describe(address))?;
+        }
+        "p" | "print" => {
+            let value =
+                Expr::parse_with_symbols(rest_of_line(line, 1), debugger.symbols())?.evaluate(cpu);
+            writeln!(output, "${:X} ({})", value, value)?;
+        }
+        "d" | "delete" => {
+            let address = address(debugger, &words, 1)?;
+            if !debugger.remove_breakpoint(address) {
+                return Err(format!("no breakpoint at ${:04X}", address).into());
+            }
+        }
+        "w" | "watch" => {
+            let address = address(debugger, &words, 1)?;
+            let kind = match words.get(2).copied().unwrap_or("w") {
+                "r" => WatchKind::Read,
+                "w" => WatchKind::Write,
+                "rw" => WatchKind::Access,
+                kind => return Err(format!("unknown watch kind: {}", kind).into()),
+            };
+            debugger.add_watchpoint(cpu, Watchpoint { address, kind });
+            writeln!(output, "watchpoint at {}", debugger.describe(address))?;
+        }
+        "unwatch" => {
+            let address = address(debugger, &words, 1)?;
+            debugger.remove_watchpoints(cpu, address);
+        }
+        "i" | "info" => {
+            for (address, breakpoint) in debugger.breakpoints() {
+                let location = debugger.describe(address);
+                let mut line = match &breakpoint.message {
+                    Some(message) => format!("tracepoint {} \"{}\"", location, message),
+                    None => format!("breakpoint {}", location),
+                };
+                if let Some(condition) = &breakpoint.condition {
+                    line += &format!(" if {}", condition);
+                }
+                line += &format!(" (hits {}", breakpoint.hits);
+                if breakpoint.ignore > 0 {
+                    line += &format!(", ignore {}", breakpoint.ignore);
+                }
+                writeln!(output, "{})", line)?;
+            }
+            for watchpoint in cpu.bus().watchpoints() {
+                let kind = match watchpoint.kind {
+                    WatchKind::Read => "r",
+                    WatchKind::Write => "w",
+                    WatchKind::Access => "rw",
+                };
+                writeln!(
+                    output,
+                    "watchpoint {} {}",
+                    debugger.describe(watchpoint.address),
+                    kind
+                )?;
+            }
+        }
+        "r" | "regs" => print_registers(cpu, output)?,
+        "set" => {
+            let name = words.get(1).ok_or("set needs a register".to_string())?;
+            let value = argument(&words, 2)?;
+            set_register(cpu, name, value)?;
+        }
+        "x" => {
+            let address = address(debugger, &words, 1)?;
+            let count = optional_argument(&words, 2, 0x40)?;
+            for row in (0..count).step_by(16) {
+                let start = address.wrapping_add(row);
+                let bytes: Vec<String> = (0..16.min(count - row))
+                    .map(|offset| format!("{:02X}", cpu.bus().peek(start.wrapping_add(offset))))
+                    .collect();
+                writeln!(output, "{:04X}: {}", start, bytes.join(" "))?;
+            }
+        }
+        "l" | "list" => {
+            let mut address = optional_address(debugger, &words, 1, cpu.pc())?;
+            let count = optional_argument(&words, 2, 8)?;
+            for _ in 0..count {
+                address = list(debugger, cpu, address, output)?;
+            }
+        }
+        "io" => match words.get(1) {
+            Some(name) => {
+                let register = super::io::register(cpu.bus(), name)
+                    .ok_or_else(|| format!("unknown I/O register: {}", name))?;
+                writeln!(output, "{}", register)?;
+            }
+            None => {
+                for register in super::io::registers(cpu.bus()) {
+                    writeln!(output, "{}", register)?;
+                }
+            }
+        },
+        "bt" => {
+            writeln!(output, "#0 {}", debugger.describe(cpu.pc()))?;
+            for (depth, frame) in debugger.call_stack().iter().rev().enumerate() {
+                writeln!(
+                    output,
+                    "#{} {} (called {})",
+                    depth + 1,
+                    debugger.describe(frame.return_address(cpu)),
+                    debugger.describe(frame.target)
+                )?;
+            }
+        }
+        "h" | "help" => writeln!(output, "{}", HELP)?,
+        "q" | "quit" => return Ok(true),
+        command => return Err(format!("unknown command: {} (try help)", command).into()),
+    }
+    Ok(false)
+}
+
+fn breakpoint_at<'d>(
+    debugger: &'d mut Debugger,
+    address: u16,
+) -> Result<&'d mut Breakpoint, String> {
+    debugger
+        .breakpoint_mut(address)
+        .ok_or_else(|| format!("no breakpoint at ${:04X}", address))
+}
+
+fn report(
+    debugger: &mut Debugger,
+    cpu: &Cpu,
+    stop: &Stop,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    match stop {
+        Stop::Step => {}
+        Stop::Breakpoint(address) => {
+            writeln!(output, "breakpoint at {}", debugger.describe(*address))?
+        }
+        Stop::Watchpoint(access) => {
+            let kind = match access.kind {
+                AccessKind::Read => "read",
+                AccessKind::Write => "write",
+            };
+            writeln!(
+                output,
+                "watchpoint: {} ${:02X} at {}",
+                kind,
+                access.value,
+                debugger.describe(access.address)
+            )?
+        }
+        Stop::Error(error) => writeln!(output, "{}", error)?,
+        Stop::Limit => writeln!(
+            output,
+            "still running after {} frames, stopped",
+            RUN_LIMIT_FRAMES
+        )?,
+    }
+    list(debugger, cpu, cpu.pc(), output)?;
+    Ok(())
+}
+
+// Prints the instruction at `address` with symbols for its operand, under its
+// label if it has one, and returns the address of the next instruction.
+fn list(debugger: &Debugger, cpu: &Cpu, address: u16, output: &mut impl Write) -> io::Result<u16> {
+    if let Some(name) = debugger.symbols().name_at(mapped_bank(address), address) {
+        writeln!(output, "{}:", name)?;
+    }
+    let instruction = cpu.disassemble(address);
+    writeln!(
+        output,
+        "{}",
+        debugger.symbols().annotate(&instruction.to_string())
+    )?;
+    Ok(instruction.next_address())
+}
+
+fn print_registers(cpu: &Cpu, output: &mut impl Write) -> io::Result<()> {
+    let value = |name| register(cpu, name).unwrap_or_default();
+    let flags: String = [("zf", 'Z'), ("nf", 'N'), ("hf", 'H'), ("cf", 'C')]
+        .iter()
+        .map(|&(name, letter)| if value(name) != 0 { letter } else { '-' })
+        .collect();
+    writeln!(
+        output,
+        "A:{:02X} F:{:02X} [{}] B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X}",
+        value("a"),
+        value("f"),
+        flags,
+        value("b"),
+        value("c"),
+        value("d"),
+        value("e"),
+        value("h"),
+        value("l"),
+        value("sp"),
+        value("pc")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_sets_registers_and_breaks() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let input =
+            "set a 3c\ntrace 101 A={a:d}\nb 103 if [$104] == 0\nc\nr\ni\nio nr52\nio joy\nq\nc\n";
+        let mut output = Vec::new();
+        run(&mut cpu, Symbols::new(), input.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("breakpoint at $0103\n0103: 00        NOP"));
+        assert!(output.contains("A:3C F:80 [Z---]"));
+        assert!(output.contains("A=60\nbreakpoint at $0103"));
+        assert!(output.contains("breakpoint $0103 if [$104] == 0 (hits 1)"));
+        assert!(output.contains("FF26 NR52 $F1 power=on active=1\n"));
+        assert!(output.contains("unknown I/O register: joy"));
+        assert_eq!(cpu.pc(), 0x0103);
+    }
+
+    #[test]
+    fn test_session_uses_symbols() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0104] = 0xCD;
+        rom[0x0105..0x0107].copy_from_slice(&[0x00, 0x02]);
+        let mut cpu = Cpu::new(None, rom);
+        let symbols = Symbols::parse("00:0102 Main\n00:0200 Update\n02:4000 Far").unwrap();
+        let input = "b Main\nc\nl 104 1\nb Far\nq\n";
+        let mut output = Vec::new();
+        run(&mut cpu, symbols, input.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("breakpoint at $0102 <Main>\nMain:\n0102: 00        NOP"));
+        assert!(output.contains("0104: CD 00 02  CALL Update"));
+        assert!(output.contains("Far is in ROM bank 2, which is not mapped"));
+    }
+}
@@ -0,0 +1,192 @@
+//! What the VRAM viewer shows: the tile set as one sheet, both background tile
+//! maps and details of the tile under the cursor.
+
+use crate::cpu::gpu::{
+    self, Gpu, BGP, LCDC, LCDC_BG_MAP, LCDC_TILE_DATA, LCDC_WINDOW_MAP, SCX, SCY, VRAM_BEGIN,
+};
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const TILE_COUNT: usize = 384;
+pub const SHEET_COLUMNS: usize = 16;
+pub const SHEET_WIDTH: usize = SHEET_COLUMNS * 8;
+pub const SHEET_HEIGHT: usize = TILE_COUNT / SHEET_COLUMNS * 8;
+pub const MAP_TILES: usize = 32;
+pub const MAP_WIDTH: usize = MAP_TILES * 8;
+
+const MAP_ADDRESSES: [usize; 2] = [0x9800, 0x9C00];
+
+/// Colours 0-3 of every tile, `SHEET_COLUMNS` to a row. No palette is applied,
+/// so sprite tiles read the same as background ones.
+pub fn tile_sheet(gpu: &Gpu) -> Vec<u8> {
+    let mut pixels = vec![0; SHEET_WIDTH * SHEET_HEIGHT];
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        let (x, y) = (index % SHEET_WIDTH, index / SHEET_WIDTH);
+        let tile = y / 8 * SHEET_COLUMNS + x / 8;
+        *pixel = gpu.tile_pixel(tile, y % 8, x % 8);
+    }
+    pixels
+}
+
+/// Shades of tile map 0 ($9800) or 1 ($9C00) as the background would draw it,
+/// with the current tile data addressing and BGP.
+pub fn tile_map(gpu: &Gpu, map: usize) -> Vec<u8> {
+    let bgp = gpu.read_register(BGP);
+    let mut pixels = vec![0; MAP_WIDTH * MAP_WIDTH];
+    for (index, pixel) in pixels.iter_mut().enumerate() {
+        let (x, y) = (index % MAP_WIDTH, index / MAP_WIDTH);
+        let tile = gpu.bg_tile_index(tile_number(gpu, map, x / 8, y / 8));
+        *pixel = gpu::shade(bgp, gpu.tile_pixel(tile, y % 8, x % 8));
+    }
+    pixels
+}
+
+fn tile_number(gpu: &Gpu, map: usize, column: usize, row: usize) -> u8 {
+    gpu.read_vram(map_address(map, column, row) - VRAM_BEGIN)
+}
+
+fn map_address(map: usize, column: usize, row: usize) -> usize {
+    MAP_ADDRESSES[map] + row * MAP_TILES + column
+}
+
+fn tile_address(tile: usize) -> usize {
+    VRAM_BEGIN + tile * 16
+}
+
+/// The tile map the background is drawn from.
+pub fn background_map(gpu: &Gpu) -> usize {
+    (gpu.read_register(LCDC) & LCDC_BG_MAP != 0) as usize
+}
+
+/// The part of the background map on screen as `(x, y, width, height)`
+/// rectangles; there are up to four, as the view wraps around the map.
+pub fn viewport(gpu: &Gpu) -> Vec<(usize, usize, usize, usize)> {
+    let spans = |start: usize, length: usize| {
+        let first = length.min(MAP_WIDTH - start);
+        let mut spans = vec![(start, first)];
+        if first < length {
+            spans.push((0, length - first));
+        }
+        spans
+    };
+    let mut rectangles = Vec::new();
+    for (y, height) in spans(gpu.read_register(SCY) as usize, SCREEN_HEIGHT) {
+        for (x, width) in spans(gpu.read_register(SCX) as usize, SCREEN_WIDTH) {
+            rectangles.push((x, y, width, height));
+        }
+    }
+    rectangles
+}
+
+/// Describes a tile of the sheet and the numbers that select it.
+pub fn tile_info(gpu: &Gpu, tile: usize) -> Vec<String> {
+    let mut lines = vec![format!("Tile {} at ${:04X}", tile, tile_address(tile))];
+    // Sprites and $8000 addressing reach the first 256 tiles, $8800
+    // addressing the last 256, with the same low byte as the number.
+    let mut users = Vec::new();
+    if tile < 256 {
+        users.push("OBJ");
+        users.push("BG $8000");
+    }
+    if tile >= 128 {
+        users.push("BG $8800");
+    }
+    lines.push(format!(
+        "Number ${:02X} for {}",
+        tile as u8,
+        users.join(", ")
+    ));
+    let uses: Vec<String> = (0..2)
+        .map(|map| {
+            let count = (0..MAP_TILES * MAP_TILES)
+                .filter(|index| {
+                    let number = tile_number(gpu, map, index % MAP_TILES, index / MAP_TILES);
+                    gpu.bg_tile_index(number) == tile
+                })
+                .count();
+            format!("${:04X} x{}", MAP_ADDRESSES[map], count)
+        })
+        .collect();
+    lines.push(format!("In maps: {}", uses.join(", ")));
+    lines
+}
+
+/// Describes an entry of tile map 0 or 1.
+pub fn map_info(gpu: &Gpu, map: usize, column: usize, row: usize) -> Vec<String> {
+    let lcdc = gpu.read_register(LCDC);
+    let mut layers = Vec::new();
+    if background_map(gpu) == map {
+        layers.push("BG");
+    }
+    if (lcdc & LCDC_WINDOW_MAP != 0) as usize == map {
+        layers.push("window");
+    }
+    if layers.is_empty() {
+        layers.push("unused");
+    }
+    let number = tile_number(gpu, map, column, row);
+    let tile = gpu.bg_tile_index(number);
+    let addressing = if lcdc & LCDC_TILE_DATA != 0 {
+        "$8000"
+    } else {
+        "$8800"
+    };
+    vec![
+        format!(
+            "Map ${:04X} ({}) entry {},{} at ${:04X}",
+            MAP_ADDRESSES[map],
+            layers.join(", "),
+            column,
+            row,
+            map_address(map, column, row)
+        ),
+        format!(
+            "Number ${:02X} -> tile {} at ${:04X} ({} addressing)",
+            number,
+            tile,
+            tile_address(tile),
+            addressing
+        ),
+        format!(
+            "Attributes: none on DMG, palette BGP ${:02X}",
+            gpu.read_register(BGP)
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_follows_addressing_and_viewport_wraps() {
+        let mut gpu = Gpu::new();
+        // Tile 1 is solid colour 3; map 1 entry 2,1 uses it.
+        for byte in 16..32 {
+            gpu.write_vram(byte, 0xFF);
+        }
+        gpu.write_vram(0x1C00 + MAP_TILES + 2, 0x01);
+        gpu.write_register(LCDC, 0x9C);
+        gpu.write_register(BGP, 0xE4);
+        gpu.write_register(SCX, 200);
+        gpu.write_register(SCY, 8);
+
+        let map = tile_map(&gpu, 1);
+        assert_eq!(map[8 * MAP_WIDTH + 16], 3);
+        assert_eq!(map[8 * MAP_WIDTH + 15], 0);
+        assert_eq!(tile_sheet(&gpu)[8], 3);
+        assert_eq!(background_map(&gpu), 1);
+        assert_eq!(viewport(&gpu), vec![(200, 8, 56, 144), (0, 8, 104, 144)]);
+        assert_eq!(
+            map_info(&gpu, 1, 2, 1)[..2],
+            [
+                "Map $9C00 (BG) entry 2,1 at $9C22",
+                "Number $01 -> tile 1 at $8010 ($8000 addressing)"
+            ]
+        );
+        assert_eq!(tile_info(&gpu, 1)[2], "In maps: $9800 x0, $9C00 x1");
+
+        gpu.write_register(LCDC, 0x88);
+        assert_eq!(tile_info(&gpu, 300)[1], "Number $2C for BG $8800");
+        assert_eq!(tile_info(&gpu, 1)[2], "In maps: $9800 x0, $9C00 x0");
+    }
+}
@@ -0,0 +1,149 @@
+use crate::cpu::serial::SerialCapture;
+use crate::cpu::{Cpu, CYCLES_PER_FRAME};
+use crate::EmuError;
+
+/// Something that ends a headless run before the frame limit.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StopCondition {
+    /// The serial output contains this text. Checked once per frame.
+    SerialText(String),
+    /// An instruction at this address is about to execute.
+    Pc(u16),
+    /// An instruction jumped to itself, e.g. `jr @` at the end of a test ROM.
+    InfiniteLoop,
+    /// `LD B,B` is about to execute. Test suites such as Mooneye use it as a
+    /// software breakpoint once results are in the registers.
+    Breakpoint,
+}
+
+pub const BREAKPOINT_OPCODE: u8 = 0x40;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StopReason {
+    FrameLimit,
+    SerialText,
+    Pc,
+    InfiniteLoop,
+    Breakpoint,
+    Error(EmuError),
+}
+
+impl StopReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StopReason::FrameLimit => "frame_limit",
+            StopReason::SerialText => "serial_text",
+            StopReason::Pc => "pc",
+            StopReason::InfiniteLoop => "infinite_loop",
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Error(_) => "error",
+        }
+    }
+}
+
+pub struct RunResult {
+    pub reason: StopReason,
+    pub frames: u32,
+    pub cycles: u64,
+}
+
+/// Steps the machine until a condition is met or `max_frames` frames have run.
+/// `serial` should be the capture installed as the machine's serial sink.
+pub fn run_until(
+    cpu: &mut Cpu,
+    serial: &SerialCapture,
+    max_frames: u32,
+    conditions: &[StopCondition],
+) -> RunResult {
+    let mut result = RunResult {
+        reason: StopReason::FrameLimit,
+        frames: 0,
+        cycles: 0,
+    };
+    let mut frame_cycles = 0;
+
+    while result.frames < max_frames {
+        let stop_pc = conditions.iter().any(|condition| match condition {
+            StopCondition::Pc(pc) => *pc == cpu.pc(),
+            _ => false,
+        });
+        if stop_pc {
+            result.reason = StopReason::Pc;
+            return result;
+        }
+        if conditions.contains(&StopCondition::Breakpoint)
+            && !cpu.is_locked()
+            && cpu.bus().read_byte(cpu.pc()) == BREAKPOINT_OPCODE
+        {
+            result.reason = StopReason::Breakpoint;
+            return result;
+        }
+
+        let info = match cpu.step() {
+            Ok(info) => info,
+            Err(error) => {
+                result.reason = StopReason::Error(error);
+                return result;
+            }
+        };
+        result.cycles += info.cycles as u64;
+
+        if info.pc == cpu.pc()
+            && !cpu.is_locked()
+            && conditions.contains(&StopCondition::InfiniteLoop)
+        {
+            result.reason = StopReason::InfiniteLoop;
+            return result;
+        }
+
+        frame_cycles += info.cycles as u32;
+        if frame_cycles >= CYCLES_PER_FRAME {
+            frame_cycles -= CYCLES_PER_FRAME;
+            result.frames += 1;
+
+            let text = serial.text();
+            let found = conditions.iter().any(|condition| match condition {
+                StopCondition::SerialText(expected) => text.contains(expected.as_str()),
+                _ => false,
+            });
+            if found {
+                result.reason = StopReason::SerialText;
+                return result;
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stops_at_pc_and_frame_limit() {
+        let serial = SerialCapture::new();
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        cpu.set_serial_sink(Box::new(serial.clone()));
+
+        let result = run_until(&mut cpu, &serial, 10, &[StopCondition::Pc(0x0110)]);
+        assert_eq!(result.reason, StopReason::Pc);
+        assert_eq!(cpu.pc(), 0x0110);
+        assert_eq!(result.cycles, 16 * 4);
+
+        let result = run_until(&mut cpu, &serial, 1, &[StopCondition::InfiniteLoop]);
+        assert_eq!(result.reason, StopReason::FrameLimit);
+        assert_eq!(result.frames, 1);
+    }
+
+    #[test]
+    fn test_stops_before_ld_b_b() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0103] = BREAKPOINT_OPCODE;
+        let serial = SerialCapture::new();
+        let mut cpu = Cpu::new(None, rom);
+
+        let result = run_until(&mut cpu, &serial, 1, &[StopCondition::Breakpoint]);
+        assert_eq!(result.reason, StopReason::Breakpoint);
+        assert_eq!(cpu.pc(), 0x0103);
+    }
+}
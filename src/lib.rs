@@ -0,0 +1,32 @@
+//! Game Boy emulator core.
+//!
+//! A machine is a [`Cpu`] built from a ROM image. Drive it with [`Cpu::step`]
+//! or [`Cpu::run_frame`], read the picture from [`Cpu::framebuffer`], drain
+//! audio from [`Cpu::audio_buffer_mut`], press buttons with [`Cpu::set_button`]
+//! and snapshot it with [`Cpu::save_state`] / [`Cpu::load_state`].
+#![allow(
+    clippy::upper_case_acronyms,
+    clippy::enum_variant_names,
+    clippy::match_single_binding
+)]
+
+pub mod commands;
+pub mod cpu;
+pub mod debugger;
+pub mod headless;
+pub mod rewind;
+pub mod rom_disasm;
+pub mod symbols;
+#[cfg(feature = "frontend")]
+pub mod viewer;
+
+pub use cpu::apu::{AudioBuffer, RecordingMode, SAMPLE_RATE};
+pub use cpu::error::EmuError;
+pub use cpu::gpu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use cpu::joypad::Button;
+pub use cpu::model::Model;
+pub use cpu::serial::{SerialCapture, SerialSink};
+pub use cpu::state::StateError;
+pub use cpu::{Cpu, MemoryBus, StepInfo, CYCLES_PER_FRAME};
+pub use rewind::Rewind;
+pub use symbols::Symbols;
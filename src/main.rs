@@ -1,17 +1,380 @@
-#![allow(clippy::upper_case_acronyms, dead_code)]
-mod cpu;
 use raylib::prelude::*;
+use rustyboy::commands;
+use rustyboy::cpu::link::LinkCable;
+use rustyboy::cpu::printer::Printer;
+use rustyboy::rewind::{self, Rewind};
+use rustyboy::viewer::{self, Viewer};
+use rustyboy::{
+    AudioBuffer, Button, Cpu, EmuError, Model, RecordingMode, SAMPLE_RATE, SCREEN_HEIGHT,
+    SCREEN_WIDTH,
+};
+use std::path::Path;
+
+const SCALE: i32 = 3;
+
+const KEYMAP: [(KeyboardKey, Button); 8] = [
+    (KeyboardKey::KEY_RIGHT, Button::Right),
+    (KeyboardKey::KEY_LEFT, Button::Left),
+    (KeyboardKey::KEY_UP, Button::Up),
+    (KeyboardKey::KEY_DOWN, Button::Down),
+    (KeyboardKey::KEY_Z, Button::A),
+    (KeyboardKey::KEY_X, Button::B),
+    (KeyboardKey::KEY_BACKSPACE, Button::Select),
+    (KeyboardKey::KEY_ENTER, Button::Start),
+];
+
+const PAUSE_KEY: KeyboardKey = KeyboardKey::KEY_P;
+// Held to run the game backwards.
+const REWIND_KEY: KeyboardKey = KeyboardKey::KEY_R;
+
+// Frames handed to raylib per stream update.
+const AUDIO_CHUNK_FRAMES: usize = 1024;
+// How far the playback rate may drift from nominal to keep the buffer near half full.
+const MAX_RATE_ADJUSTMENT: f32 = 0.005;
+
+// Pulls samples out of the emulator's ring buffer at a rate nudged by its fill
+// level, so audio neither underruns (crackles) nor grows latency against video.
+struct AudioOutput {
+    chunk: Vec<f32>,
+    previous: (f32, f32),
+    current: (f32, f32),
+    position: f32,
+}
+
+impl AudioOutput {
+    fn new() -> AudioOutput {
+        AudioOutput {
+            chunk: Vec::with_capacity(AUDIO_CHUNK_FRAMES * 2),
+            previous: (0.0, 0.0),
+            current: (0.0, 0.0),
+            position: 0.0,
+        }
+    }
+
+    fn fill_chunk(&mut self, buffer: &mut AudioBuffer) {
+        let error = buffer.fill_level() - 0.5;
+        let ratio = 1.0 + MAX_RATE_ADJUSTMENT * error * 2.0;
+
+        self.chunk.clear();
+        for _ in 0..AUDIO_CHUNK_FRAMES {
+            self.position += ratio;
+            while self.position >= 1.0 {
+                self.previous = self.current;
+                // On underrun hold the last sample instead of dropping to silence.
+                self.current = buffer.pop().unwrap_or(self.current);
+                self.position -= 1.0;
+            }
+            let (previous, current) = (self.previous, self.current);
+            self.chunk
+                .push(previous.0 + (current.0 - previous.0) * self.position);
+            self.chunk
+                .push(previous.1 + (current.1 - previous.1) * self.position);
+        }
+    }
+
+    fn update(&mut self, stream: &mut AudioStream, buffer: &mut AudioBuffer) {
+        while stream.is_processed() {
+            self.fill_chunk(buffer);
+            stream.update(&self.chunk);
+        }
+    }
+}
+
+const USAGE: &str =
+    "usage: rustyboy [--model MODEL] [--record-wav PATH] [--record-channels] [--headless FRAMES]
+                [--rewind-seconds N] [--rewind-memory MIB] [ROM]
+       rustyboy disasm [--sym PATH] ROM [-o OUTPUT]
+       rustyboy debug [--model MODEL] [--sym PATH] [--gdb PORT] ROM";
+
+#[derive(Default)]
+struct Options {
+    rom: Option<String>,
+    model: Model,
+    record_wav: Option<String>,
+    record_channels: bool,
+    headless_frames: Option<u32>,
+    rewind_frames: Option<u32>,
+    rewind_budget: Option<usize>,
+    link_listen: Option<String>,
+    link_connect: Option<String>,
+    printer_dir: Option<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--model" => options.model = args.next().ok_or("--model needs a name")?.parse()?,
+                "--record-wav" => {
+                    options.record_wav = Some(args.next().ok_or("--record-wav needs a path")?)
+                }
+                "--record-channels" => options.record_channels = true,
+                "--headless" => {
+                    let frames = args.next().ok_or("--headless needs a frame count")?;
+                    let frames = frames
+                        .parse()
+                        .map_err(|_| format!("invalid frame count: {}", frames))?;
+                    options.headless_frames = Some(frames);
+                }
+                "--rewind-seconds" => {
+                    let seconds = args.next().ok_or("--rewind-seconds needs a number")?;
+                    let frames = seconds
+                        .parse::<u32>()
+                        .ok()
+                        .and_then(|seconds| seconds.checked_mul(60))
+                        .ok_or_else(|| format!("invalid number of seconds: {}", seconds))?;
+                    options.rewind_frames = Some(frames);
+                }
+                "--rewind-memory" => {
+                    let size = args.next().ok_or("--rewind-memory needs a size in MiB")?;
+                    let budget = size
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|size| size.checked_mul(1024 * 1024))
+                        .ok_or_else(|| format!("invalid memory size: {}", size))?;
+                    options.rewind_budget = Some(budget);
+                }
+                "--link-listen" => {
+                    options.link_listen = Some(args.next().ok_or("--link-listen needs an address")?)
+                }
+                "--link-connect" => {
+                    options.link_connect =
+                        Some(args.next().ok_or("--link-connect needs an address")?)
+                }
+                "--printer" => {
+                    options.printer_dir = Some(args.next().ok_or("--printer needs a directory")?)
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+                _ => options.rom = Some(arg),
+            }
+        }
+        let serial_devices = [
+            &options.link_listen,
+            &options.link_connect,
+            &options.printer_dir,
+        ];
+        if serial_devices
+            .iter()
+            .filter(|device| device.is_some())
+            .count()
+            > 1
+        {
+            return Err("--link-listen, --link-connect and --printer are exclusive".to_string());
+        }
+        if options.record_channels && options.record_wav.is_none() {
+            return Err("--record-channels needs --record-wav".to_string());
+        }
+        Ok(options)
+    }
+}
+
+// Runs one frame. An illegal opcode is reported and the locked machine keeps
+// running; anything else stops emulation. Problems on the serial link are
+// reported too.
+fn run_frame(cpu: &mut Cpu) -> Result<(), EmuError> {
+    loop {
+        let result = cpu.run_frame();
+        if let Some(error) = cpu.take_serial_error() {
+            eprintln!("{}", error);
+        }
+        match result {
+            Err(error @ EmuError::IllegalOpcode { .. }) => eprintln!("{}", error),
+            result => return result,
+        }
+    }
+}
 
 fn main() {
-    println!("Hello, world!");
-    let mut cpu = cpu::Cpu::new(None, vec![0; 0xFFFF]);
-    let (mut rl, thread) = raylib::init().size(640, 480).title("Hello, World").build();
+    // Subcommands run before anything opens a window.
+    let mut args = std::env::args().skip(1).peekable();
+    let subcommand = args.peek().cloned();
+    let result = match subcommand.as_deref() {
+        Some("disasm") => Some(commands::disasm(args.by_ref().skip(1))),
+        Some("debug") => Some(commands::debug(args.by_ref().skip(1))),
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(error) = result {
+            eprintln!("{}\n{}", error, USAGE);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let game_rom = match &options.rom {
+        Some(path) => std::fs::read(path).unwrap_or_else(|error| {
+            eprintln!("Could not read {}: {}", path, error);
+            std::process::exit(1);
+        }),
+        None => vec![0; 0xFFFF],
+    };
+    let mut cpu = Cpu::with_model(options.model, None, game_rom);
+
+    let link = match (&options.link_listen, &options.link_connect) {
+        (Some(address), _) => Some((address, LinkCable::listen(address))),
+        (_, Some(address)) => Some((address, LinkCable::connect(address))),
+        _ => None,
+    };
+    if let Some((address, link)) = link {
+        match link {
+            Ok(link) => cpu.set_serial_sink(Box::new(link)),
+            Err(error) => {
+                eprintln!("Could not link over {}: {}", address, error);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(dir) = &options.printer_dir {
+        if let Err(error) = std::fs::create_dir_all(dir) {
+            eprintln!("Could not create {}: {}", dir, error);
+            std::process::exit(1);
+        }
+        cpu.set_serial_sink(Box::new(Printer::new(Some(Path::new(dir)))));
+    }
+
+    if let Some(path) = &options.record_wav {
+        let mode = if options.record_channels {
+            RecordingMode::PerChannel
+        } else {
+            RecordingMode::Mixed
+        };
+        if let Err(error) = cpu.start_audio_recording(path, mode) {
+            eprintln!("Could not record to {}: {}", path, error);
+            std::process::exit(1);
+        }
+    }
+
+    match options.headless_frames {
+        Some(frames) => {
+            for _ in 0..frames {
+                if let Err(error) = run_frame(&mut cpu) {
+                    eprintln!("Emulation stopped: {}", error);
+                    let _ = cpu.stop_audio_recording();
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => {
+            let frames = options
+                .rewind_frames
+                .unwrap_or(rewind::DEFAULT_HISTORY_SECONDS * 60);
+            let budget = options.rewind_budget.unwrap_or(rewind::DEFAULT_BUDGET);
+            let rewind = Rewind::new(rewind::DEFAULT_INTERVAL_FRAMES, frames, budget);
+            run_window(&mut cpu, rewind)
+        }
+    }
+
+    if let Err(error) = cpu.stop_audio_recording() {
+        eprintln!("Audio recording failed: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run_window(cpu: &mut Cpu, mut rewind: Rewind) {
+    let (width, height) = window_size(None);
+    let (mut rl, thread) = raylib::init().size(width, height).title("rustyboy").build();
+    rl.set_target_fps(60);
+
+    let image = Image::gen_image_color(SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32, Color::WHITE);
+    let mut screen = rl
+        .load_texture_from_image(&thread, &image)
+        .expect("could not create the screen texture");
+    let mut pixels = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 4];
+
+    let mut viewer = Viewer::new(&mut rl, &thread, SCREEN_WIDTH as i32 * SCALE, 0);
+    let mut paused = false;
+
+    let audio = match RaylibAudio::init_audio_device() {
+        Ok(audio) => Some(audio),
+        Err(error) => {
+            eprintln!("Audio disabled: {}", error);
+            None
+        }
+    };
+    unsafe { raylib::ffi::SetAudioStreamBufferSizeDefault(AUDIO_CHUNK_FRAMES as i32) };
+    let mut stream = audio
+        .as_ref()
+        .map(|audio| audio.new_audio_stream(SAMPLE_RATE, 32, 2));
+    if let Some(stream) = stream.as_mut() {
+        stream.play();
+    }
+    let mut audio_output = AudioOutput::new();
+    let mut stopped: Option<EmuError> = None;
 
     while !rl.window_should_close() {
+        let game_keys = !viewer.captures_keyboard();
+        for (key, button) in KEYMAP {
+            cpu.set_button(button, game_keys && rl.is_key_down(key));
+        }
+        if game_keys && rl.is_key_pressed(PAUSE_KEY) {
+            paused = !paused;
+        }
+        if viewer.handle_input(&mut rl, cpu) {
+            let (width, height) = window_size(viewer.open());
+            rl.set_window_size(width, height);
+        }
+        // Rewinding steps back one snapshot a frame and can also back out of
+        // a stop; letting go resumes from wherever it got to.
+        let rewinding = game_keys && rl.is_key_down(REWIND_KEY);
+        if rewinding {
+            match rewind.step_back(cpu) {
+                Ok(_) if !rewind.is_empty() => stopped = None,
+                Ok(_) => {}
+                Err(error) => eprintln!("Could not rewind: {}", error),
+            }
+        } else if stopped.is_none() && !paused {
+            match run_frame(cpu) {
+                Ok(()) => rewind.record(cpu),
+                Err(error) => {
+                    eprintln!("Emulation stopped: {}", error);
+                    stopped = Some(error);
+                }
+            }
+        }
+        if let Some(stream) = stream.as_mut() {
+            audio_output.update(stream, cpu.audio_buffer_mut());
+        }
+
+        viewer::shades_to_rgba(cpu.framebuffer(), &mut pixels);
+        screen.update_texture(&pixels);
+        viewer.update(cpu);
+
         let mut d = rl.begin_drawing(&thread);
 
         d.clear_background(Color::WHITE);
-        d.draw_text("Hello, world!", 12, 12, 20, Color::BLACK);
-        cpu.step();
+        d.draw_texture_ex(
+            &screen,
+            Vector2::new(0.0, 0.0),
+            0.0,
+            SCALE as f32,
+            Color::WHITE,
+        );
+        if let Some(error) = &stopped {
+            d.draw_text(&error.to_string(), 12, 40, 10, Color::RED);
+        }
+        if rewinding {
+            d.draw_text("Rewind", 12, 12, 10, Color::RED);
+        } else if paused {
+            d.draw_text("Paused", 12, 12, 10, Color::RED);
+        }
+        viewer.draw(&mut d, cpu);
+    }
+}
+
+// The game screen with the open debug panel, if any, to its right.
+fn window_size(panel: Option<viewer::Panel>) -> (i32, i32) {
+    let (width, height) = (SCREEN_WIDTH as i32 * SCALE, SCREEN_HEIGHT as i32 * SCALE);
+    match panel.map(viewer::Panel::size) {
+        Some((panel_width, panel_height)) => (width + panel_width, height.max(panel_height)),
+        None => (width, height),
     }
 }
@@ -0,0 +1,232 @@
+//! Rewind history: a bounded ring of machine snapshots taken every few frames.
+//!
+//! Only the newest snapshot is kept whole. Each older one is stored as the
+//! XOR of it and the snapshot after it, run-length encoded, which is mostly
+//! zeros between nearby frames. Stepping back undoes one delta at a time, and
+//! the oldest snapshots are dropped when the history is over its length or
+//! memory budget.
+
+use std::collections::VecDeque;
+
+use crate::{Cpu, StateError};
+
+pub const DEFAULT_INTERVAL_FRAMES: u32 = 2;
+pub const DEFAULT_HISTORY_SECONDS: u32 = 10;
+pub const DEFAULT_BUDGET: usize = 32 * 1024 * 1024;
+
+pub struct Rewind {
+    interval: u32,
+    capacity: usize,
+    budget: usize,
+    frames: u32,
+    newest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+}
+
+impl Rewind {
+    /// Snapshots every `interval` frames, keeping `history_frames` frames'
+    /// worth of them in at most `budget` bytes.
+    pub fn new(interval: u32, history_frames: u32, budget: usize) -> Rewind {
+        let interval = interval.max(1);
+        Rewind {
+            interval,
+            capacity: (history_frames / interval) as usize,
+            budget,
+            frames: 0,
+            newest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    /// Number of snapshots that can be stepped back to.
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    /// Bytes the history takes up.
+    pub fn size(&self) -> usize {
+        self.delta_bytes + self.newest.as_ref().map_or(0, Vec::len)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.newest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    /// Call once per emulated frame; takes a snapshot every `interval` frames.
+    pub fn record(&mut self, cpu: &Cpu) {
+        self.frames += 1;
+        if self.frames >= self.interval {
+            self.frames = 0;
+            self.push(cpu.save_state());
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        match self.newest.take() {
+            // States only change size when the machine does.
+            Some(newest) if newest.len() == state.len() => {
+                let delta = encode_delta(&newest, &state);
+                self.delta_bytes += delta.len();
+                self.deltas.push_back(delta);
+            }
+            Some(_) => self.clear(),
+            None => {}
+        }
+        self.newest = Some(state);
+
+        while self.len() > self.capacity.max(1) || self.size() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Loads the snapshot before the newest one and makes it the newest, so
+    /// play resumes from there. At the oldest snapshot this loads it again and
+    /// returns false; with no history it does nothing. A snapshot that fails
+    /// to load is left in the history.
+    pub fn step_back(&mut self, cpu: &mut Cpu) -> Result<bool, StateError> {
+        let Some(newest) = self.newest.as_ref() else {
+            return Ok(false);
+        };
+        let Some(delta) = self.deltas.back() else {
+            cpu.load_state(newest)?;
+            self.frames = 0;
+            return Ok(false);
+        };
+        let mut older = newest.clone();
+        apply_delta(&mut older, delta);
+        cpu.load_state(&older)?;
+        self.delta_bytes -= delta.len();
+        self.deltas.pop_back();
+        self.newest = Some(older);
+        self.frames = 0;
+        Ok(true)
+    }
+}
+
+// Deltas are runs of (unchanged count, changed count, changed bytes XORed),
+// with the counts as LEB128.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut index = 0;
+    while index < new.len() {
+        let unchanged = (index..new.len()).take_while(|&i| old[i] == new[i]).count();
+        index += unchanged;
+        let changed = (index..new.len()).take_while(|&i| old[i] != new[i]).count();
+        write_count(&mut encoded, unchanged);
+        write_count(&mut encoded, changed);
+        encoded.extend((index..index + changed).map(|i| old[i] ^ new[i]));
+        index += changed;
+    }
+    encoded
+}
+
+// Turns the newer state a delta was encoded against back into the older one.
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let (mut index, mut position) = (0, 0);
+    while position < delta.len() {
+        index += read_count(delta, &mut position);
+        let changed = read_count(delta, &mut position);
+        for (byte, xor) in state[index..index + changed]
+            .iter_mut()
+            .zip(&delta[position..position + changed])
+        {
+            *byte ^= xor;
+        }
+        index += changed;
+        position += changed;
+    }
+}
+
+fn write_count(out: &mut Vec<u8>, mut count: usize) {
+    while count >= 0x80 {
+        out.push(count as u8 | 0x80);
+        count >>= 7;
+    }
+    out.push(count as u8);
+}
+
+fn read_count(bytes: &[u8], position: &mut usize) -> usize {
+    let (mut count, mut shift) = (0, 0);
+    loop {
+        let byte = bytes[*position];
+        *position += 1;
+        count |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return count;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deltas_round_trip() {
+        let old: Vec<u8> = (0..1000).map(|i| (i % 7) as u8).collect();
+        let mut new = old.clone();
+        new[3] = 0xFF;
+        new[500..700].fill(0xAA);
+        let delta = encode_delta(&old, &new);
+        assert!(delta.len() < 220);
+        apply_delta(&mut new, &delta);
+        assert_eq!(new, old);
+    }
+
+    #[test]
+    fn test_steps_back_and_resumes() {
+        let mut cpu = Cpu::new(None, vec![0; 0x8000]);
+        let mut rewind = Rewind::new(2, 6, DEFAULT_BUDGET);
+        let mut states = Vec::new();
+        // One NOP a frame keeps every state different.
+        for frame in 0..10 {
+            cpu.step().unwrap();
+            rewind.record(&cpu);
+            if frame % 2 == 1 {
+                states.push(cpu.save_state());
+            }
+        }
+        // Six frames at one snapshot every two leaves the last three.
+        assert_eq!(rewind.len(), 3);
+
+        assert_eq!(rewind.step_back(&mut cpu), Ok(true));
+        assert_eq!(cpu.save_state(), states[3]);
+        assert_eq!(rewind.step_back(&mut cpu), Ok(true));
+        assert_eq!(rewind.step_back(&mut cpu), Ok(false));
+        assert_eq!(cpu.save_state(), states[2]);
+        assert_eq!(cpu.pc(), 0x0106);
+
+        cpu.step().unwrap();
+        rewind.record(&cpu);
+        rewind.record(&cpu);
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.step_back(&mut cpu), Ok(true));
+        assert_eq!(cpu.save_state(), states[2]);
+
+        // A snapshot that does not load stays put.
+        rewind.push(vec![0; states[2].len()]);
+        rewind.push(states[2].clone());
+        let length = rewind.len();
+        assert!(rewind.step_back(&mut cpu).is_err());
+        assert_eq!(rewind.len(), length);
+        assert_eq!(rewind.newest.as_ref(), Some(&states[2]));
+
+        let mut small = Rewind::new(1, 600, 0);
+        small.record(&cpu);
+        small.record(&cpu);
+        assert_eq!(small.len(), 1);
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::cpu::disasm::{self, Disassembly};
+use crate::cpu::error::ILLEGAL_OPCODES;
+use crate::symbols::{self, Symbols};
+
+const BANK_SIZE: usize = 0x4000;
+
+const VECTORS: [(u16, &str); 14] = [
+    (0x0000, "RST_00"),
+    (0x0008, "RST_08"),
+    (0x0010, "RST_10"),
+    (0x0018, "RST_18"),
+    (0x0020, "RST_20"),
+    (0x0028, "RST_28"),
+    (0x0030, "RST_30"),
+    (0x0038, "RST_38"),
+    (0x0040, "VBlankInterrupt"),
+    (0x0048, "LCDStatInterrupt"),
+    (0x0050, "TimerInterrupt"),
+    (0x0058, "SerialInterrupt"),
+    (0x0060, "JoypadInterrupt"),
+    (0x0100, "Entry"),
+];
+
+// Writes to this range select the ROM bank on every common MBC.
+const BANK_SELECT_BEGIN: u16 = 0x2000;
+const BANK_SELECT_END: u16 = 0x3FFF;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Mark {
+    Unknown,
+    Start,
+    Operand,
+}
+
+// A place code can run from: the bank mapped at 0x4000-0x7FFF and an address.
+#[derive(Copy, Clone)]
+struct Location {
+    bank: usize,
+    address: u16,
+}
+
+struct Walker<'a> {
+    rom: &'a [u8],
+    marks: Vec<Mark>,
+    // ROM offset -> (bank, address) of every jump, call or vector target.
+    targets: BTreeMap<usize, (usize, u16)>,
+    // ROM offset of a branch instruction -> ROM offset of its target.
+    branches: BTreeMap<usize, usize>,
+    symbols: &'a Symbols,
+}
+
+impl Walker<'_> {
+    fn banks(&self) -> usize {
+        self.rom.len().div_ceil(BANK_SIZE).max(1)
+    }
+
+    fn offset(&self, location: Location) -> Option<usize> {
+        let offset = match location.address {
+            0x0000..=0x3FFF => location.address as usize,
+            0x4000..=0x7FFF => location.bank * BANK_SIZE + location.address as usize - BANK_SIZE,
+            _ => return None,
+        };
+        (offset < self.rom.len()).then_some(offset)
+    }
+
+    fn read(&self, location: Location) -> u8 {
+        self.offset(location)
+            .map_or(0xFF, |offset| self.rom[offset])
+    }
+
+    fn add_target(&mut self, location: Location) -> Option<usize> {
+        let offset = self.offset(location)?;
+        let bank = if location.address < 0x4000 {
+            0
+        } else {
+            location.bank
+        };
+        self.targets.insert(offset, (bank, location.address));
+        Some(offset)
+    }
+
+    // Follows execution from `start`, marking every instruction it reaches.
+    fn walk(&mut self, start: Location) {
+        let mut pending = vec![start];
+        while let Some(mut location) = pending.pop() {
+            let mut loaded_a = None;
+            while let Some(offset) = self.offset(location) {
+                if self.marks[offset] != Mark::Unknown {
+                    break;
+                }
+                let instruction = disasm::disassemble(
+                    |address| {
+                        self.read(Location {
+                            bank: location.bank,
+                            address,
+                        })
+                    },
+                    location.address,
+                );
+                let opcode = instruction.bytes[0];
+                let length = instruction.bytes.len();
+                let fits = (1..length).all(|i| {
+                    let next = offset + i;
+                    next < self.rom.len()
+                        && next / BANK_SIZE == offset / BANK_SIZE
+                        && self.marks[next] == Mark::Unknown
+                });
+                if ILLEGAL_OPCODES.contains(&opcode) || !fits {
+                    break;
+                }
+                self.marks[offset] = Mark::Start;
+                for i in 1..length {
+                    self.marks[offset + i] = Mark::Operand;
+                }
+
+                let (target, continues) = flow(&instruction);
+                if let Some(address) = target {
+                    let target = Location {
+                        bank: location.bank,
+                        address,
+                    };
+                    if let Some(target_offset) = self.add_target(target) {
+                        self.branches.insert(offset, target_offset);
+                        pending.push(target);
+                    }
+                }
+                if !continues {
+                    break;
+                }
+
+                // Tracks the usual `ld a, BANK(x)` / `ld [$2000], a` pair so
+                // that code after a bank switch is read from the right bank.
+                if opcode == 0xEA {
+                    let address = u16::from_le_bytes([instruction.bytes[1], instruction.bytes[2]]);
+                    if let (Some(bank), BANK_SELECT_BEGIN..=BANK_SELECT_END) = (loaded_a, address) {
+                        location.bank = (bank as usize % self.banks()).max(1);
+                    }
+                }
+                loaded_a = (opcode == 0x3E).then(|| instruction.bytes[1]);
+                location.address = instruction.next_address();
+            }
+        }
+    }
+
+    // Symbols name code and data alike; without one, only branch targets get a
+    // label.
+    fn label(&self, offset: usize) -> Option<String> {
+        let bank = offset / BANK_SIZE;
+        let address = (offset % BANK_SIZE + if bank == 0 { 0 } else { BANK_SIZE }) as u16;
+        if self.marks[offset] != Mark::Operand {
+            if let Some(name) = self.symbols.name_at(bank as u16, address) {
+                return Some(name.to_string());
+            }
+        }
+        let (bank, address) = *self.targets.get(&offset)?;
+        if self.marks[offset] != Mark::Start {
+            return None;
+        }
+        let vector = VECTORS
+            .iter()
+            .find(|(vector, _)| bank == 0 && *vector == address);
+        Some(match vector {
+            Some((_, name)) => name.to_string(),
+            None => format!("L_{:02X}_{:04X}", bank, address),
+        })
+    }
+}
+
+// Where an instruction can branch to, and whether it can fall through to the
+// next one.
+fn flow(instruction: &Disassembly) -> (Option<u16>, bool) {
+    let bytes = &instruction.bytes;
+    let absolute = || Some(u16::from_le_bytes([bytes[1], bytes[2]]));
+    match bytes[0] {
+        0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
+            let target = instruction
+                .next_address()
+                .wrapping_add(bytes[1] as i8 as u16);
+            (Some(target), bytes[0] != 0x18)
+        }
+        0xC3 => (absolute(), false),
+        0xC2 | 0xCA | 0xD2 | 0xDA | 0xC4 | 0xCC | 0xD4 | 0xDC | 0xCD => (absolute(), true),
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+            (Some((bytes[0] & 0x38) as u16), true)
+        }
+        0xC9 | 0xD9 | 0xE9 => (None, false),
+        _ => (None, true),
+    }
+}
+
+/// Disassembles a whole cartridge into RGBDS source that assembles back to the
+/// same bytes. Code is found by following execution from the entry point and
+/// the RST and interrupt vectors; everything else is emitted as `DB` data.
+/// Bank switches are only followed where the bank number is loaded into A
+/// right before the write, so code reached through computed banks stays data.
+/// Labels come from `symbols` where it names them, and RAM symbols are
+/// defined up front so instructions can refer to them.
+pub fn disassemble_rom(rom: &[u8], symbols: &Symbols) -> String {
+    let mut walker = Walker {
+        rom,
+        marks: vec![Mark::Unknown; rom.len()],
+        targets: BTreeMap::new(),
+        branches: BTreeMap::new(),
+        symbols,
+    };
+    for (address, _) in VECTORS {
+        let location = Location { bank: 1, address };
+        if walker.add_target(location).is_some() {
+            walker.walk(location);
+        }
+    }
+
+    let mut source = String::new();
+    for (_, address, name) in symbols.locations() {
+        if address >= 0x8000 && !name.contains('.') {
+            writeln!(source, "DEF {} EQU ${:04X}", name, address).unwrap();
+        }
+    }
+    if !source.is_empty() {
+        source.push('\n');
+    }
+    for bank in 0..walker.banks() {
+        let begin = bank * BANK_SIZE;
+        let end = rom.len().min(begin + BANK_SIZE);
+        let base = if bank == 0 { 0 } else { BANK_SIZE };
+        if bank == 0 {
+            writeln!(source, "SECTION \"ROM Bank $000\", ROM0[$0000]").unwrap();
+        } else {
+            writeln!(
+                source,
+                "\nSECTION \"ROM Bank ${:03X}\", ROMX[$4000], BANK[${:03X}]",
+                bank, bank
+            )
+            .unwrap();
+        }
+
+        let mut offset = begin;
+        while offset < end {
+            let address = (base + offset - begin) as u16;
+            if let Some(label) = walker.label(offset) {
+                writeln!(source, "\n{}:", label).unwrap();
+            }
+            if walker.marks[offset] == Mark::Start {
+                let instruction =
+                    disasm::disassemble(|address| walker.read(Location { bank, address }), address);
+                writeln!(
+                    source,
+                    "    {}",
+                    instruction_source(&walker, &instruction, offset)
+                )
+                .unwrap();
+                offset += instruction.bytes.len();
+                continue;
+            }
+
+            // Data runs until the next instruction, label or 8 bytes.
+            let mut data = vec![rom[offset]];
+            offset += 1;
+            while offset < end
+                && data.len() < 8
+                && walker.marks[offset] != Mark::Start
+                && walker.label(offset).is_none()
+            {
+                data.push(rom[offset]);
+                offset += 1;
+            }
+            let data: Vec<String> = data.iter().map(|b| format!("${:02X}", b)).collect();
+            writeln!(source, "    DB {}", data.join(", ")).unwrap();
+        }
+    }
+    source
+}
+
+// The instruction text with branch targets replaced by labels and RAM
+// addresses by their symbols. STOP is written as bytes when its padding byte is
+// not zero, since RGBDS always emits $00.
+fn instruction_source(walker: &Walker, instruction: &Disassembly, offset: usize) -> String {
+    let bytes = &instruction.bytes;
+    if bytes[0] == 0x10 && bytes[1] != 0x00 {
+        return format!("DB $10, ${:02X}", bytes[1]);
+    }
+    let (target, _) = flow(instruction);
+    let label = walker
+        .branches
+        .get(&offset)
+        .and_then(|target| walker.label(*target));
+    if let (Some(target), Some(label)) = (target, label) {
+        return instruction
+            .text
+            .replace(&format!("${:04X}", target), &label);
+    }
+    // Local RAM symbols are not defined as constants, so they cannot be used.
+    let annotated = match symbols::address_operand(&instruction.text) {
+        Some((_, address)) if address >= 0x8000 => walker.symbols.annotate(&instruction.text),
+        _ => return instruction.text.clone(),
+    };
+    if annotated.contains('.') {
+        instruction.text.clone()
+    } else {
+        annotated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follows_calls_into_switched_bank() {
+        let mut rom = vec![0xFF; 3 * BANK_SIZE];
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x0104] = 0xCE;
+        rom[0x0150..0x015A]
+            .copy_from_slice(&[0x3E, 0x02, 0xEA, 0x00, 0x20, 0xCD, 0x00, 0x40, 0x18, 0xFE]);
+        rom[2 * BANK_SIZE] = 0xC9;
+
+        let source = disassemble_rom(&rom, &Symbols::new());
+        assert!(source.contains("\nEntry:\n    NOP\n    JP L_00_0150\n    DB $CE, $FF"));
+        assert!(source.contains("    LD A, $02\n    LD [$2000], A\n    CALL L_02_4000\n"));
+        assert!(source.contains("\nL_00_0158:\n    JR L_00_0158\n"));
+        assert!(source.contains(
+            "SECTION \"ROM Bank $002\", ROMX[$4000], BANK[$002]\n\nL_02_4000:\n    RET\n"
+        ));
+        assert!(source.contains("\nRST_38:\n    RST $38\n"));
+    }
+
+    #[test]
+    fn test_uses_symbols_for_labels_and_ram() {
+        let mut rom = vec![0x00; 2 * BANK_SIZE];
+        rom[0x0100..0x0103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x0150..0x0156].copy_from_slice(&[0xEA, 0x00, 0xC0, 0x18, 0xFE, 0x12]);
+        let symbols =
+            Symbols::parse("00:0100 EntryPoint\n00:0150 Main\n00:0155 Table\n00:c000 wCount")
+                .unwrap();
+
+        let source = disassemble_rom(&rom, &symbols);
+        assert!(source.starts_with("DEF wCount EQU $C000\n\nSECTION"));
+        assert!(source.contains("\nEntryPoint:\n    JP Main\n"));
+        assert!(source.contains("\nMain:\n    LD [wCount], A\n"));
+        assert!(source.contains("\nTable:\n    DB $12"));
+    }
+}
@@ -0,0 +1,188 @@
+//! Symbol files as written by `rgblink -n`: one `bank:address name` per line,
+//! with `;` comments.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+// Where each memory region starts. A symbol only describes addresses after it
+// in the same region, so the last ROM label does not swallow VRAM.
+const REGIONS: [u16; 10] = [
+    0x0000, 0x4000, 0x8000, 0xA000, 0xC000, 0xD000, 0xE000, 0xFE00, 0xFF00, 0xFF80,
+];
+
+/// The bank an address is in while the CPU runs. The cartridge is mapped as a
+/// plain 32 KiB ROM, so 0x4000-0x7FFF always holds bank 1.
+pub fn mapped_bank(address: u16) -> u16 {
+    match address {
+        0x4000..=0x7FFF => 1,
+        _ => 0,
+    }
+}
+
+/// The first `$XXXX` operand in disassembled text: where its `$` is and the
+/// address it holds.
+pub fn address_operand(text: &str) -> Option<(usize, u16)> {
+    let start = text.find('$')?;
+    let digits = &text[start + 1..];
+    let length = digits
+        .find(|c: char| !c.is_ascii_hexdigit())
+        .unwrap_or(digits.len());
+    if length != 4 {
+        return None;
+    }
+    Some((start, u16::from_str_radix(&digits[..4], 16).ok()?))
+}
+
+fn region(address: u16) -> usize {
+    REGIONS
+        .iter()
+        .rposition(|start| *start <= address)
+        .unwrap_or(0)
+}
+
+// Only switchable ROM is told apart by bank; the emulator does not bank RAM,
+// so other symbols are filed under bank 0.
+fn key(bank: u16, address: u16) -> (u16, u16) {
+    match address {
+        0x4000..=0x7FFF => (bank, address),
+        _ => (0, address),
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Symbols {
+    names: HashMap<String, (u16, u16)>,
+    locations: BTreeMap<(u16, u16), String>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let parsed = line
+                .split_once(char::is_whitespace)
+                .and_then(|(location, name)| {
+                    let (bank, address) = location.split_once(':')?;
+                    let bank = u16::from_str_radix(bank, 16).ok()?;
+                    let address = u16::from_str_radix(address, 16).ok()?;
+                    Some((bank, address, name.trim()))
+                });
+            let Some((bank, address, name)) = parsed else {
+                return Err(format!("line {}: expected `bank:address name`", number + 1));
+            };
+            symbols.insert(bank, address, name);
+        }
+        Ok(symbols)
+    }
+
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+        Symbols::parse(&text).map_err(|error| format!("{}: {}", path.display(), error))
+    }
+
+    /// `game.sym` for `game.gb`, which is where RGBDS projects usually put it.
+    pub fn path_for_rom(rom: &Path) -> PathBuf {
+        rom.with_extension("sym")
+    }
+
+    /// Where more than one symbol shares an address, the first global one is
+    /// used to name it.
+    pub fn insert(&mut self, bank: u16, address: u16, name: &str) {
+        self.names.insert(name.to_string(), (bank, address));
+        let location = self.locations.entry(key(bank, address));
+        let existing = location.or_insert_with(|| name.to_string());
+        if existing.contains('.') && !name.contains('.') {
+            *existing = name.to_string();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The bank and address of a symbol.
+    pub fn lookup(&self, name: &str) -> Option<(u16, u16)> {
+        self.names.get(name).copied()
+    }
+
+    /// The symbol at exactly this address.
+    pub fn name_at(&self, bank: u16, address: u16) -> Option<&str> {
+        self.locations.get(&key(bank, address)).map(String::as_str)
+    }
+
+    /// The address as `Symbol` or `Symbol+$offset`, from the nearest symbol at
+    /// or before it in the same region.
+    pub fn describe(&self, bank: u16, address: u16) -> Option<String> {
+        let key = key(bank, address);
+        let ((symbol_bank, symbol_address), name) = self.locations.range(..=key).next_back()?;
+        if *symbol_bank != key.0 || region(*symbol_address) != region(address) {
+            return None;
+        }
+        Some(match address - symbol_address {
+            0 => name.clone(),
+            offset => format!("{}+${:X}", name, offset),
+        })
+    }
+
+    /// Replaces `$XXXX` operands in disassembled text with the symbol at that
+    /// address, taking the bank from where the address is mapped.
+    pub fn annotate(&self, text: &str) -> String {
+        let name = address_operand(text).and_then(|(start, address)| {
+            Some((start, self.name_at(mapped_bank(address), address)?))
+        });
+        match name {
+            Some((start, name)) => format!("{}{}{}", &text[..start], name, &text[start + 5..]),
+            None => text.to_string(),
+        }
+    }
+
+    /// Every named location as `(bank, address, name)`, in address order.
+    pub fn locations(&self) -> impl Iterator<Item = (u16, u16, &str)> + '_ {
+        self.locations
+            .iter()
+            .map(|((bank, address), name)| (*bank, *address, name.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYM: &str = "\
+; File generated by rgblink
+00:0150 Main
+00:0153 Main.loop
+01:4000 BankedRoutine
+02:4000 OtherBank
+00:c000 wPlayerX
+";
+
+    #[test]
+    fn test_lookups_are_bank_aware() {
+        let symbols = Symbols::parse(SYM).unwrap();
+        assert_eq!(symbols.lookup("Main.loop"), Some((0, 0x0153)));
+        assert_eq!(symbols.name_at(2, 0x4000), Some("OtherBank"));
+        assert_eq!(
+            symbols.describe(0, 0x0155),
+            Some("Main.loop+$2".to_string())
+        );
+        assert_eq!(
+            symbols.describe(1, 0x4010),
+            Some("BankedRoutine+$10".to_string())
+        );
+        assert_eq!(symbols.describe(0, 0x8000), None);
+        assert_eq!(symbols.annotate("CALL $4000"), "CALL BankedRoutine");
+        assert_eq!(symbols.annotate("LD A, [$C000]"), "LD A, [wPlayerX]");
+        assert_eq!(symbols.annotate("LD A, $01"), "LD A, $01");
+        assert!(Symbols::parse("0150 Main").is_err());
+    }
+}
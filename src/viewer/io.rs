@@ -0,0 +1,23 @@
+use raylib::prelude::*;
+
+use super::{draw_lines, LINE_HEIGHT, MARGIN};
+use crate::debugger::expr::IO_REGISTERS;
+use crate::debugger::io;
+use crate::MemoryBus;
+
+const WIDTH: i32 = 620;
+
+// One line per register, without the JOYP alias but with wave RAM.
+pub const SIZE: (i32, i32) = (
+    WIDTH + MARGIN * 2,
+    LINE_HEIGHT * IO_REGISTERS.len() as i32 + MARGIN * 2,
+);
+
+/// Every I/O register and IE with its fields decoded, one per line.
+pub fn draw(d: &mut RaylibDrawHandle, bus: &MemoryBus, x: i32, y: i32) {
+    let lines: Vec<String> = io::registers(bus)
+        .iter()
+        .map(|register| register.to_string())
+        .collect();
+    draw_lines(d, &lines, x + MARGIN, y + MARGIN);
+}
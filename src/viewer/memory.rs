@@ -0,0 +1,165 @@
+use raylib::prelude::*;
+
+use super::{FONT_SIZE, HIGHLIGHT_COLOR, LINE_HEIGHT, MARGIN, TEXT_COLOR};
+use crate::debugger::memory::{self, Key, MemoryEditor, BYTES_PER_ROW};
+use crate::Cpu;
+
+const ROWS: u16 = 30;
+const REGION_WIDTH: i32 = 52;
+const ADDRESS_WIDTH: i32 = 36;
+const BYTE_WIDTH: i32 = 18;
+const CHAR_WIDTH: i32 = 7;
+const BYTES_X: i32 = MARGIN + REGION_WIDTH + ADDRESS_WIDTH;
+const CHARS_X: i32 = BYTES_X + BYTE_WIDTH * BYTES_PER_ROW as i32 + MARGIN;
+const ROWS_Y: i32 = MARGIN + LINE_HEIGHT;
+
+pub const SIZE: (i32, i32) = (
+    CHARS_X + CHAR_WIDTH * BYTES_PER_ROW as i32 + MARGIN,
+    ROWS_Y + LINE_HEIGHT * (ROWS as i32 + 2) + MARGIN * 2,
+);
+
+const HELP: &str = "click: select  0-F: edit  g: go to  /: search  n: next";
+// Writes fade out of the highlight over this many frames.
+const RECENT_FRAMES: u64 = 60;
+const SCROLL_ROWS: i32 = 3;
+const REGION_COLOR: Color = Color::GRAY;
+
+/// A hex and ASCII dump of the address space with the region of each row,
+/// bytes the CPU wrote recently highlighted, and a status line for edits,
+/// prompts and search results.
+pub struct MemoryViewer {
+    editor: MemoryEditor,
+}
+
+impl Default for MemoryViewer {
+    fn default() -> MemoryViewer {
+        MemoryViewer::new()
+    }
+}
+
+impl MemoryViewer {
+    pub fn new() -> MemoryViewer {
+        MemoryViewer {
+            editor: MemoryEditor::new(ROWS),
+        }
+    }
+
+    /// True while a prompt has the keyboard.
+    pub fn is_prompting(&self) -> bool {
+        self.editor.is_prompting()
+    }
+
+    pub fn handle_input(&mut self, rl: &mut RaylibHandle, cpu: &mut Cpu, x: i32, y: i32) {
+        let bus = cpu.bus_mut();
+        while let Some(c) = rl.get_char_pressed() {
+            self.editor.key(bus, Key::Char(c));
+        }
+        let mut keys = vec![
+            (KeyboardKey::KEY_PAGE_UP, Key::PageUp),
+            (KeyboardKey::KEY_PAGE_DOWN, Key::PageDown),
+        ];
+        // Enter and Backspace are also Start and Select, so they only go to
+        // the prompt while it holds the keyboard.
+        if self.editor.is_prompting() {
+            keys.push((KeyboardKey::KEY_ENTER, Key::Enter));
+            keys.push((KeyboardKey::KEY_BACKSPACE, Key::Backspace));
+        }
+        for (raylib_key, key) in keys {
+            if rl.is_key_pressed(raylib_key) {
+                self.editor.key(bus, key);
+            }
+        }
+
+        let wheel = rl.get_mouse_wheel_move();
+        if wheel != 0.0 {
+            self.editor.scroll(-wheel.signum() as i32 * SCROLL_ROWS);
+        }
+        if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT) {
+            let mouse = rl.get_mouse_position();
+            let column = (mouse.x as i32 - x - BYTES_X).div_euclid(BYTE_WIDTH);
+            let row = (mouse.y as i32 - y - ROWS_Y).div_euclid(LINE_HEIGHT);
+            if (0..BYTES_PER_ROW as i32).contains(&column) && (0..ROWS as i32).contains(&row) {
+                let offset = (row * BYTES_PER_ROW as i32 + column) as u16;
+                self.editor.select(self.editor.top().wrapping_add(offset));
+            }
+        }
+    }
+
+    pub fn draw(&self, d: &mut RaylibDrawHandle, cpu: &Cpu, x: i32, y: i32) {
+        let bus = cpu.bus();
+        let frame = bus.gpu().frames();
+        d.draw_text(HELP, x + MARGIN, y + MARGIN, FONT_SIZE, TEXT_COLOR);
+
+        let mut previous_region = String::new();
+        for row in 0..ROWS {
+            let start = self.editor.top().wrapping_add(row * BYTES_PER_ROW);
+            let row_y = y + ROWS_Y + row as i32 * LINE_HEIGHT;
+            let region = memory::region(start);
+            if region != previous_region {
+                d.draw_text(&region, x + MARGIN, row_y, FONT_SIZE, REGION_COLOR);
+            }
+            previous_region = region;
+            let address = format!("{:04X}", start);
+            d.draw_text(
+                &address,
+                x + MARGIN + REGION_WIDTH,
+                row_y,
+                FONT_SIZE,
+                TEXT_COLOR,
+            );
+
+            for column in 0..BYTES_PER_ROW {
+                let address = start.wrapping_add(column);
+                let value = bus.peek(address);
+                let byte_x = x + BYTES_X + column as i32 * BYTE_WIDTH;
+                let (cell_x, cell_y, cell_width) = (byte_x - 2, row_y - 1, BYTE_WIDTH - 2);
+                let age = bus
+                    .last_write_frame(address)
+                    .map(|written| frame.saturating_sub(written));
+                if let Some(age @ 0..RECENT_FRAMES) = age {
+                    let alpha = 255 - (age * 255 / RECENT_FRAMES) as u8;
+                    let color = Color::new(0xFF, 0xC0, 0x00, alpha);
+                    d.draw_rectangle(cell_x, cell_y, cell_width, LINE_HEIGHT, color);
+                }
+                if address == self.editor.cursor() {
+                    d.draw_rectangle_lines(
+                        cell_x,
+                        cell_y,
+                        cell_width,
+                        LINE_HEIGHT,
+                        HIGHLIGHT_COLOR,
+                    );
+                }
+                d.draw_text(
+                    &format!("{:02X}", value),
+                    byte_x,
+                    row_y,
+                    FONT_SIZE,
+                    TEXT_COLOR,
+                );
+                let c = if value.is_ascii_graphic() {
+                    value as char
+                } else {
+                    '.'
+                };
+                let char_x = x + CHARS_X + column as i32 * CHAR_WIDTH;
+                d.draw_text(&c.to_string(), char_x, row_y, FONT_SIZE, TEXT_COLOR);
+            }
+        }
+
+        let status_y = y + ROWS_Y + ROWS as i32 * LINE_HEIGHT + MARGIN;
+        let selected = format!(
+            "${:04X} {}",
+            self.editor.cursor(),
+            memory::region(self.editor.cursor())
+        );
+        d.draw_text(&selected, x + MARGIN, status_y, FONT_SIZE, TEXT_COLOR);
+        d.draw_text(
+            &self.editor.status(),
+            x + MARGIN,
+            status_y + LINE_HEIGHT,
+            FONT_SIZE,
+            TEXT_COLOR,
+        );
+    }
+}
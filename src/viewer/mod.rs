@@ -0,0 +1,173 @@
+//! Debug panels the frontend draws to the right of the game screen.
+
+pub mod io;
+pub mod memory;
+pub mod oam;
+pub mod vram;
+
+use raylib::prelude::*;
+
+use self::memory::MemoryViewer;
+use self::oam::OamViewer;
+use self::vram::VramViewer;
+use crate::Cpu;
+
+// Shades 0-3 as RGBA, lightest first.
+pub const PALETTE: [[u8; 4]; 4] = [
+    [0xE0, 0xF8, 0xD0, 0xFF],
+    [0x88, 0xC0, 0x70, 0xFF],
+    [0x34, 0x68, 0x56, 0xFF],
+    [0x08, 0x18, 0x20, 0xFF],
+];
+
+const MARGIN: i32 = 8;
+const FONT_SIZE: i32 = 10;
+const LINE_HEIGHT: i32 = 12;
+const TEXT_COLOR: Color = Color::BLACK;
+const HIGHLIGHT_COLOR: Color = Color::RED;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Panel {
+    Vram,
+    Oam,
+    Memory,
+    Io,
+}
+
+impl Panel {
+    const ALL: [Panel; 4] = [Panel::Vram, Panel::Oam, Panel::Memory, Panel::Io];
+
+    fn key(self) -> KeyboardKey {
+        match self {
+            Panel::Vram => KeyboardKey::KEY_F1,
+            Panel::Oam => KeyboardKey::KEY_F2,
+            Panel::Memory => KeyboardKey::KEY_F3,
+            Panel::Io => KeyboardKey::KEY_F4,
+        }
+    }
+
+    pub fn size(self) -> (i32, i32) {
+        match self {
+            Panel::Vram => vram::SIZE,
+            Panel::Oam => oam::SIZE,
+            Panel::Memory => memory::SIZE,
+            Panel::Io => io::SIZE,
+        }
+    }
+}
+
+/// The open panel, if any, and what each panel keeps between frames. Panels
+/// are drawn with their top left corner at `x`, `y`.
+pub struct Viewer {
+    open: Option<Panel>,
+    x: i32,
+    y: i32,
+    vram: VramViewer,
+    oam: OamViewer,
+    memory: MemoryViewer,
+}
+
+impl Viewer {
+    pub fn new(rl: &mut RaylibHandle, thread: &RaylibThread, x: i32, y: i32) -> Viewer {
+        Viewer {
+            open: None,
+            x,
+            y,
+            vram: VramViewer::new(rl, thread),
+            oam: OamViewer::new(rl, thread),
+            memory: MemoryViewer::new(),
+        }
+    }
+
+    pub fn open(&self) -> Option<Panel> {
+        self.open
+    }
+
+    /// True when the open panel takes keys that are also game buttons.
+    pub fn captures_keyboard(&self) -> bool {
+        self.open == Some(Panel::Memory) && self.memory.is_prompting()
+    }
+
+    /// Opens or closes panels on their key and passes other input to the open
+    /// panel; returns true when which panel is open changed.
+    pub fn handle_input(&mut self, rl: &mut RaylibHandle, cpu: &mut Cpu) -> bool {
+        for panel in Panel::ALL {
+            if rl.is_key_pressed(panel.key()) {
+                self.open = if self.open == Some(panel) {
+                    None
+                } else {
+                    Some(panel)
+                };
+                cpu.bus_mut()
+                    .set_write_tracking(self.open == Some(Panel::Memory));
+                return true;
+            }
+        }
+        if self.open == Some(Panel::Memory) {
+            self.memory.handle_input(rl, cpu, self.x, self.y);
+        }
+        false
+    }
+
+    /// Brings the open panel's textures up to date; call before drawing.
+    pub fn update(&mut self, cpu: &Cpu) {
+        match self.open {
+            Some(Panel::Vram) => self.vram.update(cpu.bus().gpu()),
+            Some(Panel::Oam) => self.oam.update(cpu.bus().gpu()),
+            Some(Panel::Memory | Panel::Io) | None => {}
+        }
+    }
+
+    pub fn draw(&self, d: &mut RaylibDrawHandle, cpu: &Cpu) {
+        let (x, y) = (self.x, self.y);
+        match self.open {
+            Some(Panel::Vram) => self.vram.draw(d, cpu.bus().gpu(), x, y),
+            Some(Panel::Oam) => self.oam.draw(d, cpu.bus().gpu(), x, y),
+            Some(Panel::Memory) => self.memory.draw(d, cpu, x, y),
+            Some(Panel::Io) => io::draw(d, cpu.bus(), x, y),
+            None => {}
+        }
+    }
+}
+
+/// Copies shades into an RGBA buffer through `PALETTE`.
+pub fn shades_to_rgba(shades: &[u8], pixels: &mut [u8]) {
+    for (pixel, shade) in pixels.chunks_exact_mut(4).zip(shades) {
+        pixel.copy_from_slice(&PALETTE[*shade as usize]);
+    }
+}
+
+fn texture(rl: &mut RaylibHandle, thread: &RaylibThread, width: usize, height: usize) -> Texture2D {
+    let image = Image::gen_image_color(width as i32, height as i32, Color::WHITE);
+    rl.load_texture_from_image(thread, &image)
+        .expect("could not create a viewer texture")
+}
+
+fn draw_lines(d: &mut RaylibDrawHandle, lines: &[String], x: i32, y: i32) {
+    for (index, line) in lines.iter().enumerate() {
+        d.draw_text(
+            line,
+            x,
+            y + index as i32 * LINE_HEIGHT,
+            FONT_SIZE,
+            TEXT_COLOR,
+        );
+    }
+}
+
+// The cell of a `columns` x `rows` grid of `cell`-pixel squares at `x`, `y`
+// that the mouse is over.
+fn hovered_cell(
+    d: &RaylibDrawHandle,
+    x: i32,
+    y: i32,
+    cell: i32,
+    columns: usize,
+    rows: usize,
+) -> Option<(usize, usize)> {
+    let mouse = d.get_mouse_position();
+    let column = (mouse.x as i32 - x).div_euclid(cell);
+    let row = (mouse.y as i32 - y).div_euclid(cell);
+    let inside = (0..columns as i32).contains(&column) && (0..rows as i32).contains(&row);
+    inside.then_some((column as usize, row as usize))
+}
@@ -0,0 +1,86 @@
+use raylib::prelude::*;
+
+use super::{texture, FONT_SIZE, LINE_HEIGHT, MARGIN, PALETTE, TEXT_COLOR};
+use crate::cpu::gpu::Gpu;
+use crate::debugger::oam::{self, SPRITE_COUNT};
+
+const ROWS: usize = SPRITE_COUNT / 2;
+const ROW_HEIGHT: i32 = 20;
+const COLUMN_WIDTH: i32 = 380;
+const PREVIEW_WIDTH: usize = 8;
+const PREVIEW_HEIGHT: usize = 16;
+
+pub const SIZE: (i32, i32) = (
+    COLUMN_WIDTH * 2 + MARGIN * 3,
+    LINE_HEIGHT + ROW_HEIGHT * ROWS as i32 + MARGIN * 2,
+);
+
+const HEADER: &str = "#  X    Y    tile flags (B)ehind, Y/X flip, OBP";
+const PREVIEW_BACKGROUND: Color = Color::LIGHTGRAY;
+const DROPPED_COLOR: Color = Color::RED;
+const OFFSCREEN_COLOR: Color = Color::GRAY;
+
+/// All 40 OAM entries in two columns, each with its sprite as drawn. Entries
+/// the 10-per-line limit drops somewhere are red, offscreen ones grey.
+pub struct OamViewer {
+    previews: Texture2D,
+    pixels: Vec<u8>,
+}
+
+impl OamViewer {
+    pub fn new(rl: &mut RaylibHandle, thread: &RaylibThread) -> OamViewer {
+        OamViewer {
+            previews: texture(rl, thread, PREVIEW_WIDTH * SPRITE_COUNT, PREVIEW_HEIGHT),
+            pixels: vec![0; PREVIEW_WIDTH * SPRITE_COUNT * PREVIEW_HEIGHT * 4],
+        }
+    }
+
+    // The previews sit side by side in one texture; 8x8 sprites leave the
+    // bottom half transparent.
+    pub fn update(&mut self, gpu: &Gpu) {
+        self.pixels.fill(0);
+        let stride = PREVIEW_WIDTH * SPRITE_COUNT;
+        for sprite in oam::sprites(gpu) {
+            for (index, shade) in oam::preview(gpu, &sprite).into_iter().enumerate() {
+                let (x, y) = (index % PREVIEW_WIDTH, index / PREVIEW_WIDTH);
+                if let Some(shade) = shade {
+                    let offset = (y * stride + sprite.index * PREVIEW_WIDTH + x) * 4;
+                    self.pixels[offset..offset + 4].copy_from_slice(&PALETTE[shade as usize]);
+                }
+            }
+        }
+        self.previews.update_texture(&self.pixels);
+    }
+
+    pub fn draw(&self, d: &mut RaylibDrawHandle, gpu: &Gpu, x: i32, y: i32) {
+        d.draw_text(HEADER, x + MARGIN, y + MARGIN, FONT_SIZE, TEXT_COLOR);
+        let height = gpu.sprite_height() as i32;
+        for sprite in oam::sprites(gpu) {
+            let left = x + MARGIN + (sprite.index / ROWS) as i32 * (COLUMN_WIDTH + MARGIN);
+            let top = y + MARGIN + LINE_HEIGHT + (sprite.index % ROWS) as i32 * ROW_HEIGHT;
+            d.draw_rectangle(left, top, PREVIEW_WIDTH as i32, height, PREVIEW_BACKGROUND);
+            let source = Rectangle::new(
+                (sprite.index * PREVIEW_WIDTH) as f32,
+                0.0,
+                PREVIEW_WIDTH as f32,
+                height as f32,
+            );
+            d.draw_texture_rec(
+                &self.previews,
+                source,
+                Vector2::new(left as f32, top as f32),
+                Color::WHITE,
+            );
+
+            let color = if !sprite.dropped.is_empty() {
+                DROPPED_COLOR
+            } else if sprite.lines.is_none() {
+                OFFSCREEN_COLOR
+            } else {
+                TEXT_COLOR
+            };
+            let text_x = left + PREVIEW_WIDTH as i32 + MARGIN;
+            d.draw_text(&sprite.to_string(), text_x, top, FONT_SIZE, color);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use raylib::prelude::*;
+
+use super::{draw_lines, hovered_cell, shades_to_rgba, texture, HIGHLIGHT_COLOR, MARGIN};
+use crate::cpu::gpu::Gpu;
+use crate::debugger::vram::{
+    self, MAP_TILES, MAP_WIDTH, SHEET_COLUMNS, SHEET_HEIGHT, SHEET_WIDTH, TILE_COUNT,
+};
+
+// The sheet is drawn at twice the size of the maps, whose 256 pixels fit twice
+// over in the height.
+const SHEET_SCALE: i32 = 2;
+const SHEET_SIZE: (i32, i32) = (
+    SHEET_WIDTH as i32 * SHEET_SCALE,
+    SHEET_HEIGHT as i32 * SHEET_SCALE,
+);
+const MAP_SIZE: i32 = MAP_WIDTH as i32;
+
+pub const SIZE: (i32, i32) = (
+    SHEET_SIZE.0 + MAP_SIZE + MARGIN * 3,
+    MAP_SIZE * 2 + MARGIN * 3,
+);
+
+const VIEWPORT_COLOR: Color = Color::BLUE;
+
+/// The tile sheet on the left with details of the hovered tile under it, and
+/// the tile maps at $9800 and $9C00 on the right.
+pub struct VramViewer {
+    sheet: Texture2D,
+    maps: [Texture2D; 2],
+    pixels: Vec<u8>,
+}
+
+impl VramViewer {
+    pub fn new(rl: &mut RaylibHandle, thread: &RaylibThread) -> VramViewer {
+        VramViewer {
+            sheet: texture(rl, thread, SHEET_WIDTH, SHEET_HEIGHT),
+            maps: [
+                texture(rl, thread, MAP_WIDTH, MAP_WIDTH),
+                texture(rl, thread, MAP_WIDTH, MAP_WIDTH),
+            ],
+            pixels: vec![0; MAP_WIDTH * MAP_WIDTH * 4],
+        }
+    }
+
+    pub fn update(&mut self, gpu: &Gpu) {
+        let sheet = vram::tile_sheet(gpu);
+        let pixels = &mut self.pixels[..sheet.len() * 4];
+        shades_to_rgba(&sheet, pixels);
+        self.sheet.update_texture(pixels);
+        for (map, texture) in self.maps.iter_mut().enumerate() {
+            shades_to_rgba(&vram::tile_map(gpu, map), &mut self.pixels);
+            texture.update_texture(&self.pixels);
+        }
+    }
+
+    pub fn draw(&self, d: &mut RaylibDrawHandle, gpu: &Gpu, x: i32, y: i32) {
+        let sheet = (x + MARGIN, y + MARGIN);
+        d.draw_texture_ex(
+            &self.sheet,
+            Vector2::new(sheet.0 as f32, sheet.1 as f32),
+            0.0,
+            SHEET_SCALE as f32,
+            Color::WHITE,
+        );
+        let maps = [0, 1].map(|map| {
+            (
+                sheet.0 + SHEET_SIZE.0 + MARGIN,
+                y + MARGIN + map * (MAP_SIZE + MARGIN),
+            )
+        });
+        for (texture, (map_x, map_y)) in self.maps.iter().zip(maps) {
+            d.draw_texture(texture, map_x, map_y, Color::WHITE);
+        }
+
+        let (map_x, map_y) = maps[vram::background_map(gpu)];
+        for (left, top, width, height) in vram::viewport(gpu) {
+            d.draw_rectangle_lines(
+                map_x + left as i32,
+                map_y + top as i32,
+                width as i32,
+                height as i32,
+                VIEWPORT_COLOR,
+            );
+        }
+
+        let tile_size = 8 * SHEET_SCALE;
+        let rows = TILE_COUNT / SHEET_COLUMNS;
+        let mut info = Vec::new();
+        if let Some((column, row)) =
+            hovered_cell(d, sheet.0, sheet.1, tile_size, SHEET_COLUMNS, rows)
+        {
+            let (left, top) = (
+                sheet.0 + column as i32 * tile_size,
+                sheet.1 + row as i32 * tile_size,
+            );
+            d.draw_rectangle_lines(left, top, tile_size, tile_size, HIGHLIGHT_COLOR);
+            info = vram::tile_info(gpu, row * SHEET_COLUMNS + column);
+        }
+        for (map, (map_x, map_y)) in maps.into_iter().enumerate() {
+            if let Some((column, row)) = hovered_cell(d, map_x, map_y, 8, MAP_TILES, MAP_TILES) {
+                d.draw_rectangle_lines(
+                    map_x + column as i32 * 8,
+                    map_y + row as i32 * 8,
+                    8,
+                    8,
+                    HIGHLIGHT_COLOR,
+                );
+                info = vram::map_info(gpu, map, column, row);
+            }
+        }
+        draw_lines(d, &info, sheet.0, sheet.1 + SHEET_SIZE.1 + MARGIN);
+    }
+}
@@ -0,0 +1,110 @@
+//! Runs Blargg's cpu_instrs and instr_timing ROMs when `RUSTYBOY_BLARGG_DIR`
+//! points at a checkout of the gb-test-roms collection.
+
+mod common;
+
+use std::path::Path;
+
+use rustyboy::headless::{run_until, StopCondition, StopReason};
+use rustyboy::Cpu;
+
+const ROM_DIR_VAR: &str = "RUSTYBOY_BLARGG_DIR";
+const SUITES: [&str; 2] = ["cpu_instrs/individual", "instr_timing"];
+
+// The slowest cpu_instrs ROM needs around 30 seconds of emulated time.
+const MAX_FRAMES: u32 = 60 * 60;
+
+// Newer Blargg ROMs also report through cartridge RAM: 0xA001..0xA004 hold
+// this signature, 0xA000 the status and 0xA004 onwards the text.
+const SIGNATURE_ADDRESS: u16 = 0xA001;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const STATUS_ADDRESS: u16 = 0xA000;
+const STATUS_RUNNING: u8 = 0x80;
+const TEXT_ADDRESS: u16 = 0xA004;
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed(String),
+    TimedOut(String),
+    Crashed(String),
+}
+
+fn memory_status(cpu: &Cpu) -> Option<u8> {
+    let bus = cpu.bus();
+    let signature_found = SIGNATURE
+        .iter()
+        .enumerate()
+        .all(|(offset, byte)| bus.read_byte(SIGNATURE_ADDRESS + offset as u16) == *byte);
+    signature_found.then(|| bus.read_byte(STATUS_ADDRESS))
+}
+
+fn memory_text(cpu: &Cpu) -> String {
+    let bytes: Vec<u8> = (TEXT_ADDRESS..0xBFFF)
+        .map(|address| cpu.bus().read_byte(address))
+        .take_while(|byte| *byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn run_rom(path: &Path) -> Outcome {
+    let (mut cpu, serial) = common::load_rom(path);
+    let conditions = [
+        StopCondition::SerialText("Passed".to_string()),
+        StopCondition::SerialText("Failed".to_string()),
+    ];
+
+    for _ in 0..MAX_FRAMES {
+        let result = run_until(&mut cpu, &serial, 1, &conditions);
+        let text = serial.text();
+        match result.reason {
+            StopReason::SerialText if text.contains("Passed") => return Outcome::Passed,
+            StopReason::SerialText => return Outcome::Failed(text),
+            StopReason::Error(error) => return Outcome::Crashed(error.to_string()),
+            _ => {}
+        }
+        match memory_status(&cpu) {
+            Some(0) => return Outcome::Passed,
+            Some(STATUS_RUNNING) | None => {}
+            Some(status) => {
+                return Outcome::Failed(format!("status {}: {}", status, memory_text(&cpu)))
+            }
+        }
+    }
+    Outcome::TimedOut(serial.text())
+}
+
+#[test]
+fn blargg_cpu_instrs_and_instr_timing() {
+    let Some(root) = common::rom_dir(ROM_DIR_VAR) else {
+        return;
+    };
+
+    let mut failures = Vec::new();
+    let mut count = 0;
+    for suite in SUITES {
+        for rom in common::find_roms(&root.join(suite)) {
+            let name = common::display_name(&rom, &root);
+            let outcome = run_rom(&rom);
+            count += 1;
+            match &outcome {
+                Outcome::Passed => println!("pass  {}", name),
+                Outcome::Failed(detail) => println!("FAIL  {}: {}", name, detail.trim()),
+                Outcome::TimedOut(detail) => println!("TIME  {}: {}", name, detail.trim()),
+                Outcome::Crashed(detail) => println!("CRASH {}: {}", name, detail),
+            }
+            if outcome != Outcome::Passed {
+                failures.push(name);
+            }
+        }
+    }
+
+    assert!(count > 0, "no ROMs found under {}", root.display());
+    assert!(
+        failures.is_empty(),
+        "{} of {} Blargg ROMs failed: {}",
+        failures.len(),
+        count,
+        failures.join(", ")
+    );
+}
@@ -0,0 +1,58 @@
+// Helpers shared by the ROM-driven integration tests. Each test binary uses a
+// different subset of them.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use rustyboy::{Cpu, SerialCapture};
+
+/// Directory named by `var`, or `None` (with a note on stderr) when the ROMs
+/// have not been supplied and the test should be skipped.
+pub fn rom_dir(var: &str) -> Option<PathBuf> {
+    match std::env::var_os(var) {
+        Some(dir) if Path::new(&dir).is_dir() => Some(PathBuf::from(dir)),
+        _ => {
+            eprintln!("skipping: set {} to a directory of test ROMs", var);
+            None
+        }
+    }
+}
+
+/// Every `.gb` file under `dir`, recursively, in sorted order.
+pub fn find_roms(dir: &Path) -> Vec<PathBuf> {
+    let mut roms = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|extension| extension == "gb") {
+                roms.push(path);
+            }
+        }
+    }
+    roms.sort();
+    roms
+}
+
+/// A machine with the ROM loaded, ready to run from the cartridge entry point,
+/// and a handle on everything it sends over the serial port.
+pub fn load_rom(path: &Path) -> (Cpu, SerialCapture) {
+    let rom = std::fs::read(path)
+        .unwrap_or_else(|error| panic!("could not read {}: {}", path.display(), error));
+    let serial = SerialCapture::new();
+    let mut cpu = Cpu::new(None, rom);
+    cpu.set_serial_sink(Box::new(serial.clone()));
+    (cpu, serial)
+}
+
+pub fn display_name(path: &Path, root: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
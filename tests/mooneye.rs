@@ -0,0 +1,108 @@
+//! Runs every ROM under `RUSTYBOY_MOONEYE_DIR` (e.g. a Mooneye test suite build)
+//! and prints a summary table. Tests finish on `LD B,B` with the Fibonacci
+//! numbers 3/5/8/13/21/34 in B/C/D/E/H/L on success, or 0x42 everywhere on
+//! failure.
+
+mod common;
+
+use std::path::Path;
+
+use rustyboy::cpu::registers::Registers;
+use rustyboy::headless::{run_until, StopCondition, StopReason};
+
+const ROM_DIR_VAR: &str = "RUSTYBOY_MOONEYE_DIR";
+const MAX_FRAMES: u32 = 60 * 20;
+
+const PASS_SIGNATURE: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const FAIL_SIGNATURE: [u8; 6] = [0x42; 6];
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed,
+    BadSignature([u8; 6]),
+    TimedOut,
+    Crashed(String),
+}
+
+impl Outcome {
+    fn label(&self) -> &'static str {
+        match self {
+            Outcome::Passed => "pass",
+            Outcome::Failed => "FAIL",
+            Outcome::BadSignature(_) => "SIG?",
+            Outcome::TimedOut => "TIME",
+            Outcome::Crashed(_) => "CRASH",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            Outcome::BadSignature(registers) => format!("{:02X?}", registers),
+            Outcome::Crashed(error) => error.clone(),
+            _ => String::new(),
+        }
+    }
+}
+
+fn signature(registers: &Registers) -> [u8; 6] {
+    [
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+    ]
+}
+
+fn run_rom(path: &Path) -> Outcome {
+    let (mut cpu, serial) = common::load_rom(path);
+    let result = run_until(&mut cpu, &serial, MAX_FRAMES, &[StopCondition::Breakpoint]);
+    match result.reason {
+        StopReason::Breakpoint => match signature(cpu.registers()) {
+            PASS_SIGNATURE => Outcome::Passed,
+            FAIL_SIGNATURE => Outcome::Failed,
+            other => Outcome::BadSignature(other),
+        },
+        StopReason::Error(error) => Outcome::Crashed(error.to_string()),
+        _ => Outcome::TimedOut,
+    }
+}
+
+#[test]
+fn mooneye_test_suite() {
+    let Some(root) = common::rom_dir(ROM_DIR_VAR) else {
+        return;
+    };
+    let roms = common::find_roms(&root);
+    assert!(!roms.is_empty(), "no ROMs found under {}", root.display());
+
+    let results: Vec<(String, Outcome)> = roms
+        .iter()
+        .map(|rom| (common::display_name(rom, &root), run_rom(rom)))
+        .collect();
+
+    let width = results
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    println!("{:<width$}  result  detail", "rom", width = width);
+    for (name, outcome) in &results {
+        println!(
+            "{:<width$}  {:<6}  {}",
+            name,
+            outcome.label(),
+            outcome.detail(),
+            width = width
+        );
+    }
+    let passed = results
+        .iter()
+        .filter(|(_, outcome)| *outcome == Outcome::Passed)
+        .count();
+    println!("{} of {} passed", passed, results.len());
+
+    assert_eq!(passed, results.len(), "some Mooneye tests failed");
+}
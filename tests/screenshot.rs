@@ -0,0 +1,151 @@
+//! Compares the screen of each ROM under `RUSTYBOY_SCREENSHOT_DIR` with a
+//! reference PNG of the same name next to it, e.g. `dmg-acid2.gb` and
+//! `dmg-acid2.png`. ROMs without a reference are skipped. Each ROM runs until
+//! `LD B,B`, as dmg-acid2 and Mealybug Tearoom do when the picture is done, or
+//! for `MAX_FRAMES` frames. Mismatches leave a diff image in the test's
+//! scratch directory: matching pixels faded, differing ones red.
+
+mod common;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use rustyboy::cpu::image::SHADES;
+use rustyboy::headless::{run_until, StopCondition, StopReason};
+use rustyboy::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const ROM_DIR_VAR: &str = "RUSTYBOY_SCREENSHOT_DIR";
+const MAX_FRAMES: u32 = 60 * 10;
+
+// Reference images are decoded to gray and matched to the nearest DMG shade,
+// so greenish or RGB references compare the same as grayscale ones.
+fn load_reference(path: &Path) -> Result<Vec<u8>, String> {
+    let file = File::open(path).map_err(|error| error.to_string())?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(|error| error.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader
+        .next_frame(&mut buffer)
+        .map_err(|error| error.to_string())?;
+    if frame.width as usize != SCREEN_WIDTH || frame.height as usize != SCREEN_HEIGHT {
+        return Err(format!(
+            "reference is {}x{}, expected {}x{}",
+            frame.width, frame.height, SCREEN_WIDTH, SCREEN_HEIGHT
+        ));
+    }
+
+    let channels = frame.color_type.samples();
+    let gray = |pixel: &[u8]| -> u32 {
+        match channels {
+            1 | 2 => pixel[0] as u32,
+            _ => (pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000,
+        }
+    };
+    Ok(buffer[..frame.buffer_size()]
+        .chunks_exact(channels)
+        .map(|pixel| {
+            let level = gray(pixel);
+            (0..SHADES.len())
+                .min_by_key(|shade| level.abs_diff(SHADES[*shade] as u32))
+                .unwrap() as u8
+        })
+        .collect())
+}
+
+fn save_diff(path: &Path, actual: &[u8], expected: &[u8]) -> std::io::Result<()> {
+    let pixels: Vec<u8> = actual
+        .iter()
+        .zip(expected)
+        .flat_map(|(actual, expected)| {
+            if actual == expected {
+                let faded = 0xC0 + SHADES[*actual as usize] / 4;
+                [faded, faded, faded]
+            } else {
+                [0xFF, 0x00, 0x00]
+            }
+        })
+        .collect();
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+    writer
+        .write_image_data(&pixels)
+        .map_err(std::io::Error::other)?;
+    writer.finish().map_err(std::io::Error::other)
+}
+
+// Returns a description of the failure, if any.
+fn check_rom(rom: &Path, reference: &Path, diff_dir: &Path) -> Option<String> {
+    let expected = match load_reference(reference) {
+        Ok(expected) => expected,
+        Err(error) => return Some(format!("bad reference: {}", error)),
+    };
+
+    let (mut cpu, serial) = common::load_rom(rom);
+    let result = run_until(&mut cpu, &serial, MAX_FRAMES, &[StopCondition::Breakpoint]);
+    if let StopReason::Error(error) = result.reason {
+        return Some(error.to_string());
+    }
+
+    let actual = cpu.framebuffer();
+    let mismatched = actual
+        .iter()
+        .zip(&expected)
+        .filter(|(actual, expected)| actual != expected)
+        .count();
+    if mismatched == 0 {
+        return None;
+    }
+
+    let name = rom.file_stem().unwrap_or_default().to_string_lossy();
+    let diff = diff_dir.join(format!("{}.diff.png", name));
+    let saved = match save_diff(&diff, actual, &expected) {
+        Ok(()) => format!("diff in {}", diff.display()),
+        Err(error) => format!("could not save diff: {}", error),
+    };
+    Some(format!("{} pixels differ, {}", mismatched, saved))
+}
+
+#[test]
+fn screenshots_match_references() {
+    let Some(root) = common::rom_dir(ROM_DIR_VAR) else {
+        return;
+    };
+    let diff_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("screenshots");
+    std::fs::create_dir_all(&diff_dir).unwrap();
+
+    let mut failures = Vec::new();
+    let mut count = 0;
+    for rom in common::find_roms(&root) {
+        let reference = rom.with_extension("png");
+        if !reference.exists() {
+            continue;
+        }
+        count += 1;
+        let name = common::display_name(&rom, &root);
+        match check_rom(&rom, &reference, &diff_dir) {
+            None => println!("pass  {}", name),
+            Some(failure) => {
+                println!("FAIL  {}: {}", name, failure);
+                failures.push(name);
+            }
+        }
+    }
+
+    assert!(
+        count > 0,
+        "no ROMs with references under {}",
+        root.display()
+    );
+    assert!(
+        failures.is_empty(),
+        "{} of {} screenshots differ: {}",
+        failures.len(),
+        count,
+        failures.join(", ")
+    );
+}
@@ -0,0 +1,259 @@
+//! Per-opcode conformance against the SingleStepTests SM83 vectors, run when
+//! `RUSTYBOY_SM83_TESTS_DIR` points at a directory of their JSON files.
+//!
+//! The vectors start with the opcode already fetched: `initial.pc` points just
+//! past it and the last bus cycle prefetches the next opcode. The harness
+//! rewinds PC by one, runs the instruction with `Cpu::step` on flat memory and
+//! converts its bus trace to the same view before comparing the bus activity
+//! of every M-cycle, idle ones included.
+
+mod common;
+
+use std::path::Path;
+
+use rustyboy::cpu::registers::FlagsRegister;
+use rustyboy::cpu::{AccessKind, BusAccess};
+use rustyboy::Cpu;
+use serde_json::Value;
+
+const ROM_DIR_VAR: &str = "RUSTYBOY_SM83_TESTS_DIR";
+
+// Failures printed per opcode file; the rest are only counted.
+const REPORTED_FAILURES: usize = 3;
+
+const REGISTERS: [&str; 8] = ["a", "b", "c", "d", "e", "f", "h", "l"];
+
+fn number(state: &Value, key: &str) -> u16 {
+    state[key]
+        .as_u64()
+        .unwrap_or_else(|| panic!("missing field {}", key)) as u16
+}
+
+fn ram(state: &Value) -> Vec<(u16, u8)> {
+    state["ram"]
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .map(|entry| {
+                    (
+                        entry[0].as_u64().unwrap() as u16,
+                        entry[1].as_u64().unwrap() as u8,
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// One entry per M-cycle. Idle cycles are null or have neither pin set; the
+// rest read like `[address, value, "r-m"]`.
+fn expected_cycles(test: &Value) -> Vec<Option<BusAccess>> {
+    let Some(cycles) = test["cycles"].as_array() else {
+        return Vec::new();
+    };
+    cycles
+        .iter()
+        .map(|cycle| {
+            let address = cycle.get(0)?.as_u64()? as u16;
+            let value = cycle.get(1)?.as_u64()? as u8;
+            let pins = cycle.get(2)?.as_str()?;
+            let kind = if pins.contains('w') {
+                AccessKind::Write
+            } else if pins.contains('r') {
+                AccessKind::Read
+            } else {
+                return None;
+            };
+            Some(BusAccess {
+                address,
+                value,
+                kind,
+            })
+        })
+        .collect()
+}
+
+fn describe_cycle(cycle: &Option<BusAccess>) -> String {
+    match cycle {
+        Some(access) => format!(
+            "{:?} {:04X}={:02X}",
+            access.kind, access.address, access.value
+        ),
+        None => "idle".to_string(),
+    }
+}
+
+fn register(cpu: &Cpu, name: &str) -> u8 {
+    let registers = cpu.registers();
+    match name {
+        "a" => registers.a,
+        "b" => registers.b,
+        "c" => registers.c,
+        "d" => registers.d,
+        "e" => registers.e,
+        "f" => u8::from(registers.f),
+        "h" => registers.h,
+        _ => registers.l,
+    }
+}
+
+fn set_register(cpu: &mut Cpu, name: &str, value: u8) {
+    let registers = cpu.registers_mut();
+    match name {
+        "a" => registers.a = value,
+        "b" => registers.b = value,
+        "c" => registers.c = value,
+        "d" => registers.d = value,
+        "e" => registers.e = value,
+        "f" => registers.f = FlagsRegister::from(value),
+        "h" => registers.h = value,
+        _ => registers.l = value,
+    }
+}
+
+// Runs one vector and returns every difference from the expected final state.
+fn run_test(cpu: &mut Cpu, test: &Value) -> Vec<String> {
+    let initial = &test["initial"];
+    let expected = &test["final"];
+    for name in REGISTERS {
+        set_register(cpu, name, number(initial, name) as u8);
+    }
+    cpu.set_sp(number(initial, "sp"));
+    cpu.set_pc(number(initial, "pc").wrapping_sub(1));
+    let initial_ram = ram(initial);
+    for (address, value) in &initial_ram {
+        cpu.bus_mut().write_byte(*address, *value);
+    }
+
+    cpu.bus_mut().start_trace();
+    let stepped = cpu.step();
+    let mut trace = cpu.bus_mut().take_trace();
+
+    let mut differences = Vec::new();
+    if let Err(error) = stepped {
+        differences.push(error.to_string());
+    } else {
+        for name in REGISTERS {
+            let (actual, wanted) = (register(cpu, name), number(expected, name) as u8);
+            if actual != wanted {
+                differences.push(format!("{}: {:02X}, expected {:02X}", name, actual, wanted));
+            }
+        }
+        let pc = cpu.pc().wrapping_add(1);
+        for (name, actual) in [("pc", pc), ("sp", cpu.sp())] {
+            let wanted = number(expected, name);
+            if actual != wanted {
+                differences.push(format!("{}: {:04X}, expected {:04X}", name, actual, wanted));
+            }
+        }
+        for (address, wanted) in ram(expected) {
+            let actual = cpu.bus().read_byte(address);
+            if actual != wanted {
+                differences.push(format!(
+                    "[{:04X}]: {:02X}, expected {:02X}",
+                    address, actual, wanted
+                ));
+            }
+        }
+
+        // Drop our opcode fetch and add the prefetch of the next opcode.
+        if !trace.is_empty() {
+            trace.remove(0);
+        }
+        trace.push(Some(BusAccess {
+            address: cpu.pc(),
+            value: cpu.bus().peek(cpu.pc()),
+            kind: AccessKind::Read,
+        }));
+        let wanted = expected_cycles(test);
+        if trace.len() != wanted.len() {
+            differences.push(format!(
+                "{} M-cycles, expected {}",
+                trace.len(),
+                wanted.len()
+            ));
+        }
+        for (cycle, (actual, wanted)) in trace.iter().zip(&wanted).enumerate() {
+            if actual != wanted {
+                differences.push(format!(
+                    "cycle {}: {}, expected {}",
+                    cycle + 1,
+                    describe_cycle(actual),
+                    describe_cycle(wanted)
+                ));
+            }
+        }
+    }
+
+    // Leave the machine clean for the next vector.
+    for (address, _) in initial_ram.iter().chain(&ram(expected)) {
+        cpu.bus_mut().write_byte(*address, 0);
+    }
+    for access in trace.iter().flatten() {
+        cpu.bus_mut().write_byte(access.address, 0);
+    }
+    differences
+}
+
+// Returns (passed, total).
+fn run_file(cpu: &mut Cpu, path: &Path) -> (usize, usize) {
+    let json = std::fs::read_to_string(path)
+        .unwrap_or_else(|error| panic!("could not read {}: {}", path.display(), error));
+    let tests: Vec<Value> = serde_json::from_str(&json)
+        .unwrap_or_else(|error| panic!("could not parse {}: {}", path.display(), error));
+
+    let mut passed = 0;
+    let mut reported = 0;
+    for test in &tests {
+        let differences = run_test(cpu, test);
+        if differences.is_empty() {
+            passed += 1;
+        } else if reported < REPORTED_FAILURES {
+            reported += 1;
+            println!("  {}:", test["name"].as_str().unwrap_or("?"));
+            for difference in differences {
+                println!("    {}", difference);
+            }
+        }
+    }
+    (passed, tests.len())
+}
+
+#[test]
+fn sm83_single_step_tests() {
+    let Some(dir) = common::rom_dir(ROM_DIR_VAR) else {
+        return;
+    };
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .expect("could not read the test directory")
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no JSON files in {:?}", dir);
+
+    let mut cpu = Cpu::new(None, Vec::new());
+    cpu.bus_mut().set_flat(true);
+
+    let mut failed_files = Vec::new();
+    for path in &files {
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let (passed, total) = run_file(&mut cpu, path);
+        println!("{:<6} {:>4}/{}", name, passed, total);
+        if passed != total {
+            failed_files.push(name.into_owned());
+        }
+    }
+    assert!(
+        failed_files.is_empty(),
+        "{} of {} opcodes have failing vectors: {}",
+        failed_files.len(),
+        files.len(),
+        failed_files.join(", ")
+    );
+}